@@ -1,12 +1,149 @@
 use image::{ImageBuffer, Rgba, RgbaImage};
 use imageproc::{self, drawing::Canvas, point::Point, rect::Rect};
 use macroquad::prelude::{camera::mouse, scene::camera_pos, *};
-use std::{cmp::Ordering, collections::VecDeque};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    ops::ControlFlow,
+    path::PathBuf,
+    str::FromStr,
+    sync::{mpsc, Arc},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 const DRAW_COLOR: [u8; 4] = [88, 96, 117, 255];
+const MATERIALS: [Material; 4] = [
+    Material {
+        name: "Default",
+        color: DRAW_COLOR,
+        restitution: 0.5,
+        friction: 0.3,
+    },
+    Material {
+        name: "Rubber",
+        color: [196, 84, 96, 255],
+        restitution: 0.9,
+        friction: 0.6,
+    },
+    Material {
+        name: "Clay",
+        color: [171, 128, 84, 255],
+        restitution: 0.05,
+        friction: 0.9,
+    },
+    Material {
+        name: "Ice",
+        color: [122, 186, 214, 255],
+        restitution: 0.3,
+        friction: 0.02,
+    },
+];
 const MIN_TOOL_RADIUS: f32 = 1.;
 const MAX_TOOL_RADIUS: f32 = 175.;
-const TOOL_SIZING_FACTOR: f32 = 0.05;
+const TOOL_SIZING_FACTOR: f32 = 0.05; // Default scroll sensitivity for resizing tools
+const MIN_TOOL_SIZING_FACTOR: f32 = 0.005;
+const MAX_TOOL_SIZING_FACTOR: f32 = 1.;
+const TETHER_STIFFNESS: f32 = 10000.; // Stiffness given to tethers of newly built lattices
+const FORCE_TOOL_STRENGTH: f32 = 10000.; // Force tool strength per unit of tool radius
+const BURST_SPEED: f32 = 600.; // Speed an explosion/implosion gives particles at its center, in pixels per second
+const STIFFNESS_PAINT_STEP: f32 = 1.03; // Per-frame stiffness multiplier of the stiffness brush
+const MIN_TETHER_LENGTH: f32 = 1e-4; // Tethers shorter than this apply no force
+const MIN_TETHER_STIFFNESS: f32 = TETHER_STIFFNESS / 10.;
+const MAX_TETHER_STIFFNESS: f32 = TETHER_STIFFNESS * 10.;
+const PARTICLE_COLLISION_RADIUS: f32 = 3.;
+const COLLISION_STIFFNESS: f32 = 5000.; // Penalty force per unit of overlap
+const COLLISION_GROUP_COLORS: [u32; 5] = [0xf2df50, 0x50c8f2, 0xf25078, 0x78f250, 0xb450f2]; // Particle color per group
+const SUBSTEP_STRAIN_THRESHOLD: f32 = 0.2; // Tether strain above which frames get extra substeps
+const MAX_SUBSTEPS: u32 = 8;
+const MAX_DT: f32 = 1. / 30.; // Default longest frame time simulated, so a stall doesn't blow up stiff tethers
+const MAX_DT_LIMIT: f32 = 0.25; // Upper bound on max_dt when loaded from the config
+const PBD_ITERATIONS: u32 = 10; // Default constraint projection passes per step in position-based mode
+const MAX_PBD_ITERATIONS: u32 = 50;
+const QUANTIZE_SPACING: f32 = 2.; // Default grid particles are snapped to before rebuilding tethers
+const MIN_QUANTIZE_SPACING: f32 = 0.5;
+const MAX_QUANTIZE_SPACING: f32 = 50.;
+const SLEEP_SPEED: f32 = 2.; // Default speed in pixels per second below which a particle starts to fall asleep
+const SLEEP_STEPS: u32 = 60; // Steps a particle has to stay that slow before it sleeps
+const WAKE_ACCELERATION: f32 = 50.; // Net acceleration in pixels per second squared that wakes a particle
+const MAX_GLOBAL_DAMPING: f32 = 20.; // Fraction of velocity lost per second is 1 - e^-damping
+const GLOBAL_DAMPING_STEP: f32 = 0.5;
+const COLLISION_ITERATIONS: u32 = 1; // Default impulse collision passes per step, more settle deep stacks
+const MAX_COLLISION_ITERATIONS: u32 = 20;
+const BODY_MASS: f32 = 100.; // Mass each body is normalized to when mass normalization is on
+const MOUSE_SPRING_STIFFNESS: f32 = 200.;
+const LAUNCH_VELOCITY_SCALE: f32 = 3.; // Launch speed in pixels per second per pixel dragged
+const TOOL_PRESET_COUNT: usize = 4; // Presets on Alt + 1-4
+const MIN_MOUSE_SPRING_STIFFNESS: f32 = 10.;
+const MAX_MOUSE_SPRING_STIFFNESS: f32 = 5000.;
+const MOUSE_SPRING_SCROLL_STEP: f32 = 1.1; // Stiffness factor per scroll notch while holding a particle
+const MOUSE_SPRING_FULL_TENSION: f32 = 20000.; // Pull at which the spring line is drawn fully red
+const FLOOR_SPEED: f32 = 200.; // Pixels per second the floor moves while its keys are held
+const MUSCLE_AMPLITUDE: f32 = 0.2; // Default fraction of the rest length a muscle contracts/expands by
+const MUSCLE_FREQUENCY: f32 = 1.; // Default muscle cycles per second
+const SMOOTHING_SIGMA: f32 = 2.; // Blur radius used when smoothing drawn edges
+const GRID_SPACING: f32 = 20.; // Default distance between grid lines in Create mode
+const MIN_GRID_SPACING: f32 = 5.;
+const MAX_GRID_SPACING: f32 = 100.;
+const GRID_SPACING_STEP: f32 = 5.;
+const FILL_PATTERN_SIZE: u32 = 24; // Checker square and hatch stripe size, a few lattice spacings so the holes survive sampling
+const DRAWING_SCALE_STEP: f32 = 1.1; // Factor the drawing grows/shrinks by per key press
+const GRAVITY: f32 = 500.; // Downward acceleration in pixels per second squared
+const GRAVITY_DRAG_SCALE: f32 = 5.; // Gravity per pixel dragged when setting it with the mouse
+const ANCHOR_BAND: f32 = 2.; // How far below a body's top a particle can be and still count as its top row
+const LATTICE_SPACING: f32 = 10.; // Hexagon radius or square side of built lattices
+const DEGREE_COLORS: [u32; 8] = [
+    0xE73D3D, 0xE7713D, 0xE7C23D, 0x9BE73D, 0x3DE7A5, 0x3D9BE7, 0x713DE7, 0xE73DC2,
+]; // Particles colored by tether count, the last for that many or more
+const DISPLACEMENT_COLOR_SCALE: f32 = 50.; // Displacement in pixels drawn fully red in the displacement field
+const MAX_CANVAS_SIZE: u32 = 4096; // Largest canvas resolution accepted from the config
+const PARTICLE_RENDER_RADIUS: f32 = 1.5;
+const MIN_PARTICLE_RENDER_RADIUS: f32 = 0.5;
+const MAX_PARTICLE_RENDER_RADIUS: f32 = 6.;
+const TARGET_FPS_CHOICES: [f32; 5] = [30., 45., 60., 90., 120.]; // Shift + F9 cycles the adaptive quality target
+const ADAPTIVE_DROP_DELAY: f64 = 1.; // Seconds over budget before adaptive quality steps down
+const ADAPTIVE_RESTORE_DELAY: f64 = 3.; // Seconds of headroom before it steps back up
+const ADAPTIVE_RESTORE_HEADROOM: f32 = 0.6; // Fraction of the frame budget the work must fit in to step up
+const CIRCLE_SEGMENTS: u8 = 20; // Sides per particle circle, fewer draw large scenes faster
+const MIN_CIRCLE_SEGMENTS: u8 = 3;
+const MAX_CIRCLE_SEGMENTS: u8 = 64;
+const TETHER_WIDTH: f32 = 1.; // Multiplier on the stiffness-based tether line width
+const MIN_TETHER_WIDTH: f32 = 0.25;
+const MAX_TETHER_WIDTH: f32 = 4.;
+const TETHER_WIDTH_STEP: f32 = 0.25;
+const ROOM_TEMPERATURE: f32 = 20.;
+const TEMPERATURE_RATE: f32 = 100.; // Degrees per second while heating/cooling
+const THERMAL_SOFTENING: f32 = 0.002; // Fraction of stiffness lost per degree above room temperature
+const MIN_THERMAL_STIFFNESS: f32 = 0.05; // Clamp on the stiffness multiplier so the material never loses all stiffness
+const MAX_THERMAL_STIFFNESS: f32 = 3.;
+const GRID_CELL_SIZE: f32 = 10.; // Side length of spatial grid cells, at least the collision diameter
+const LAYER_COUNT: usize = 4;
+const CULL_MARGIN: f32 = 10.; // Extra space around the view before objects are skipped in rendering
+const LINE_BATCH_QUADS: usize = 800; // Lines per mesh, keeping its indices under macroquad's 5000 per draw call
+const NOTICE_DURATION: f64 = 1.5; // Seconds a notice stays in the info bar
+const FORCE_HISTORY_LEN: usize = 200;
+const ENERGY_HISTORY_LEN: usize = 300; // Frames of kinetic energy kept for the graph (about 5 seconds)
+const WELD_DISTANCE: f32 = 0.01; // Particles closer than this after building are merged into one
+const DECIMATION_SPACING: f32 = 1.5; // Grid cell size when thinning a lattice, in average tether lengths
+const SHATTER_THRESHOLD: f32 = 300.; // Default sudden change in a region's velocity, in pixels per second, that shatters it
+const MIN_SHATTER_THRESHOLD: f32 = 50.;
+const MAX_SHATTER_THRESHOLD: f32 = 5000.;
+const SHATTER_THRESHOLD_STEP: f32 = 1.25; // Factor the threshold changes by per key press
+const SHATTER_REGION_SIZE: f32 = 30.; // Side length of the regions impacts are measured over
+const SHARD_SIZE: f32 = 10.; // Shattered regions break into pieces about this big
+const DELAUNAY_REFRESH_DISTANCE: f32 = 2.; // How far any particle moves before the triangulation is redone
+const CONFIG_PATH: &str = "config.toml";
+const STAMP_DIRECTORY: &str = "stamps";
+const OBSTACLE_PATH: &str = "obstacles.txt";
+const MESH_PATH: &str = "mesh.json";
+const TIMELINE_PATH: &str = "timeline.txt";
+const WIND_DRAG: f32 = 0.5; // How quickly particles are dragged up to the wind's speed, per second
+const EXPORT_SCALE: f32 = 4.; // Resolution of exported stills relative to the canvas
+const MAX_EXPORT_SIZE: f32 = 8192.; // Longest side of an exported still, the scale is lowered to fit
+const MESH_FORMAT: &str = "hexagonal_softbodies.mesh"; // Identifies interchange files, see export_mesh_json
+const MESH_SCHEMA_VERSION: u32 = 1;
+const BACKGROUND_COLORS: [u32; 4] = [0x0E131F, 0x000000, 0xFFFFFF, 0x00B140]; // Cycled through, the last is for chroma keying
 
 #[derive(Clone, Copy)]
 enum Mode {
@@ -25,49 +162,164 @@ fn config_window() -> Conf {
 }
 
 fn render(mode: Mode, game_state: &mut GameState, physics_objects: &(Vec<Particle>, Vec<Tether>)) {
-    let w = screen_width();
-    let h = screen_height();
-    let mouse_x = mouse_position().0;
-    let mouse_y = mouse_position().1;
+    let Vec2 { x: w, y: h } = game_state.canvas_size();
+    let (mouse_x, mouse_y) = game_state.canvas_mouse();
 
     // Draw the info bar
-    let bar_height = 27_f32;
-    draw_rectangle(
-        0.,
-        h - bar_height,
-        w,
-        bar_height,
-        Color::from_rgba(0, 0, 0, 50),
-    );
+    if game_state.show_ui {
+        let bar_height = 27_f32;
+        draw_rectangle(
+            0.,
+            h - bar_height,
+            w,
+            bar_height,
+            Color::from_rgba(0, 0, 0, 50),
+        );
+    }
 
-    // Render the physics objects
-    physics_objects.1.iter().for_each(|tether| {
-        tether.render(&physics_objects.0);
-    });
-    physics_objects.0.iter().for_each(|particle| {
-        particle.render();
-    });
+    // Show the latest notice on the right of the info bar for a moment (even with the UI hidden)
+    if let Some((text, time)) = &game_state.notice {
+        if get_time() - time < NOTICE_DURATION {
+            let width = measure_text(text, None, 23, 1.).width;
+            draw_text(text, w - width - 8., h - 8., 23., Color::from_hex(0xEDE54C));
+        }
+    }
+
+    // Show the radius being typed next to the cursor
+    if let Some(entry) = &game_state.radius_entry {
+        let text = format!("Radius: {}_  (Enter/Esc)", entry);
+        let width = measure_text(&text, None, 20, 1.).width;
+        draw_rectangle(
+            mouse_x + 12.,
+            mouse_y - 30.,
+            width + 8.,
+            24.,
+            Color::from_rgba(0, 0, 0, 160),
+        );
+        draw_text(
+            &text,
+            mouse_x + 16.,
+            mouse_y - 12.,
+            20.,
+            Color::from_hex(0xEDE54C),
+        );
+    }
+
+    // Draw the floor
+    if game_state.floor_enabled {
+        draw_line(
+            0.,
+            game_state.floor_y,
+            w,
+            game_state.floor_y,
+            2.,
+            Color::from_hex(0x777A84),
+        );
+    }
+
+    // Draw the obstacle outlines
+    for obstacle in game_state.obstacles.iter() {
+        for i in 0..obstacle.len() {
+            let (a, b) = (obstacle[i], obstacle[(i + 1) % obstacle.len()]);
+            draw_line(a.x, a.y, b.x, b.y, 2., Color::from_hex(0x777A84));
+        }
+    }
+
+    // Draw the captured ghost faintly behind the live objects
+    if let Some(ghost) = game_state
+        .ghost
+        .as_ref()
+        .filter(|_| game_state.ghost_visible)
+    {
+        let ghost_color = Color::from_rgba(61, 155, 231, 70);
+        // The tethers only line up with the ghost while the particles haven't been rebuilt
+        if ghost.len() == physics_objects.0.len() {
+            let mut batch = LineBatch::new();
+            physics_objects.1.iter().for_each(|tether| {
+                batch.line(
+                    ghost[tether.p1_index],
+                    ghost[tether.p2_index],
+                    0.5,
+                    ghost_color,
+                );
+            });
+            batch.flush();
+        }
+        ghost.iter().for_each(|p| {
+            draw_circle(p.x, p.y, game_state.particle_render_radius, ghost_color);
+        });
+    }
+
+    // Draw the undeformed rest shape faintly behind the live objects
+    if game_state.show_rest_shape {
+        let rest_color = Color::from_rgba(203, 206, 209, 45);
+        let mut batch = LineBatch::new();
+        physics_objects.1.iter().for_each(|tether| {
+            batch.line(
+                physics_objects.0[tether.p1_index].rest_position,
+                physics_objects.0[tether.p2_index].rest_position,
+                0.5,
+                rest_color,
+            );
+        });
+        batch.flush();
+    }
+
+    render_physics_objects(physics_objects, game_state.render_style());
+
+    // The same world stepped with the other integrator, to the right
+    if let Some(comparison) = &game_state.comparison {
+        let frame_color = Color::from_rgba(203, 206, 209, 90);
+        let label_color = Color::from_rgba(203, 206, 209, 200);
+        draw_rectangle_lines(0., 0., w, h, 2., frame_color);
+        draw_text(game_state.integrator.name(), 0., -12., 36., label_color);
+
+        set_camera(&game_state.comparison_camera());
+        draw_rectangle_lines(0., 0., w, h, 2., frame_color);
+        draw_text(
+            game_state.integrator.other().name(),
+            0.,
+            -12.,
+            36.,
+            label_color,
+        );
+        render_physics_objects(&comparison.physics_objects, game_state.render_style());
+        set_camera(&game_state.canvas_camera());
+    }
+
+    // Leave only the simulation/drawing for clean captures
+    if !game_state.show_ui {
+        return;
+    }
+
+    // Compact summary of the active tool, just above the info bar
+    render_tool_hud(mode, game_state, w - 230., h - 27. - 10.);
 
     // Draw mode specific details
     match mode {
         Mode::Create => {
             // Render create-mode relevant things
+            if game_state.show_grid {
+                render_grid(game_state.grid_spacing, vec2(w, h));
+            }
+            let (mouse_x, mouse_y) = if game_state.snap_to_grid {
+                snap_to_grid((mouse_x, mouse_y), game_state.grid_spacing)
+            } else {
+                (mouse_x, mouse_y)
+            };
 
             // Render the brush size indicators
             match game_state.draw_mode {
                 DrawMode::Add => {
-                    draw_circle_lines(
-                        mouse_x,
-                        mouse_y,
-                        game_state.add_radius,
-                        1.,
+                    draw_circle_lines(mouse_x, mouse_y, game_state.add_radius, 1., {
+                        let color = MATERIALS[game_state.draw_material].color;
                         Color::from_rgba(
-                            DRAW_COLOR[0] + 50,
-                            DRAW_COLOR[1] + 50,
-                            DRAW_COLOR[2] + 50,
-                            DRAW_COLOR[3],
-                        ),
-                    );
+                            color[0].saturating_add(50),
+                            color[1].saturating_add(50),
+                            color[2].saturating_add(50),
+                            color[3],
+                        )
+                    });
                 }
                 DrawMode::Remove => {
                     draw_circle_lines(
@@ -85,6 +337,24 @@ fn render(mode: Mode, game_state: &mut GameState, physics_objects: &(Vec<Particl
                 }
             };
 
+            // Point new users at the brush until there is something to build from
+            if game_state.canvas_empty && physics_objects.0.is_empty() {
+                let lines = [
+                    "Hold the left mouse button to draw a body",
+                    "then press (Enter) to build its lattice",
+                ];
+                for (i, line) in lines.iter().enumerate() {
+                    let width = measure_text(line, None, 30, 1.).width;
+                    draw_text(
+                        line,
+                        (w - width) / 2.,
+                        h / 2. + 34. * i as f32,
+                        30.,
+                        Color::from_rgba(203, 206, 209, 140),
+                    );
+                }
+            }
+
             // Render the UI
             draw_text(
                 "Create Mode",
@@ -100,37 +370,101 @@ fn render(mode: Mode, game_state: &mut GameState, physics_objects: &(Vec<Particl
                 23.,
                 Color::from_rgba(203, 206, 209, 170),
             );
-            draw_text(
-                "- (Enter) Compute Lattice",
-                9.,
-                80.,
-                18.,
-                Color::from_rgba(203, 206, 209, 140),
-            );
-            draw_text(
-                "- (Backspace) Clear",
-                9.,
-                100.,
-                18.,
-                Color::from_rgba(203, 206, 209, 140),
-            );
-            draw_text(
-                "- (Q) Switch Brush (Add/Remove)",
-                9.,
-                120.,
-                18.,
-                Color::from_rgba(203, 206, 209, 140),
-            );
-            draw_text(
-                "- (F) Fill",
-                9.,
-                140.,
-                18.,
-                Color::from_rgba(203, 206, 209, 140),
-            );
+            let help_lines = [
+                "- (Enter) Compute Lattice, (Shift + Enter) Add Newly Drawn Parts".to_owned(),
+                "- (Esc) Save Settings and Quit".to_owned(),
+                "- (Alt + 1-4) Tool Preset, (Alt + Shift + 1-4) Save Tool As Preset".to_owned(),
+                "- (Backspace) Clear Layer, (Del) Clear Physics, (Ctrl + Backspace) Clear All"
+                    .to_owned(),
+                "- (Q) Switch Brush (Add/Remove)".to_owned(),
+                format!(
+                    "- (F) Fill, (Shift + F) Pattern: {}",
+                    game_state.fill_pattern.name()
+                ),
+                format!(
+                    "- (G) Grid: {}, (Shift + G) Snap: {}, (,/.) Spacing: {:.0}",
+                    if game_state.show_grid { "On" } else { "Off" },
+                    if game_state.snap_to_grid { "On" } else { "Off" },
+                    game_state.grid_spacing
+                ),
+                format!(
+                    "- (Ctrl + 1-{}) Draw Material: {}",
+                    MATERIALS.len(),
+                    MATERIALS[game_state.draw_material].name
+                ),
+                "- (Hold Arrow Keys) Draw Straight Lines Along Them".to_owned(),
+                "- (C) Center Drawing, (I) Invert Drawing".to_owned(),
+                "- (X/Y) Mirror Drawing Left-Right/Top-Bottom, (Shift + X/Y) Flip It".to_owned(),
+                "- (S) Smooth Edges, (Ctrl + S) Save As Stamp".to_owned(),
+                "- ([/]) Shrink/Grow Drawing".to_owned(),
+                format!(
+                    "- (1-{}) Layer: {}, (Shift + 1-{}) Toggle Visibility [{}]",
+                    LAYER_COUNT,
+                    game_state.active_layer + 1,
+                    LAYER_COUNT,
+                    game_state
+                        .layers
+                        .iter()
+                        .map(|layer| if layer.visible { "on" } else { "off" })
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                ),
+                format!(
+                    "- (N) Build Lattice From: {}",
+                    if game_state.build_from_empty {
+                        "Empty Space (drawing and fills make holes)"
+                    } else {
+                        "Drawing"
+                    }
+                ),
+                format!(
+                    "- (L) Lattice: {}, (Shift + L) Brace Squares: {}",
+                    game_state.lattice_type.name(),
+                    if game_state.brace_squares {
+                        "On"
+                    } else {
+                        "Off"
+                    }
+                ),
+                format!(
+                    "- (O) Remove Orphan Particles: {}",
+                    if game_state.remove_orphans {
+                        "On"
+                    } else {
+                        "Off"
+                    }
+                ),
+                format!(
+                    "- (W) Weld Duplicate Particles: {}",
+                    if game_state.weld_duplicates {
+                        "On"
+                    } else {
+                        "Off"
+                    }
+                ),
+                format!(
+                    "- (M) Normalize Body Mass: {}",
+                    if game_state.normalize_mass {
+                        "On"
+                    } else {
+                        "Off"
+                    }
+                ),
+            ];
+            for (i, line) in help_lines.iter().enumerate() {
+                draw_text(
+                    line,
+                    9.,
+                    80. + 20. * i as f32,
+                    18.,
+                    Color::from_rgba(203, 206, 209, 140),
+                );
+            }
+
+            render_material_palette(game_state.draw_material, 8., h - 62.);
 
             draw_text(
-                "Scroll to change tool sizes.",
+                "Scroll or Tab to change tool sizes. [Ctrl +/- for sensitivity. -- H to hide UI.]",
                 8.,
                 h - 8.,
                 23.,
@@ -147,650 +481,7615 @@ fn render(mode: Mode, game_state: &mut GameState, physics_objects: &(Vec<Particl
                 23.,
                 Color::from_rgba(203, 206, 209, 140),
             );
+            let help_lines = [
+                format!("- (Q) Switch Tool: {}", game_state.sim_tool.name()),
+                format!(
+                    "- (Enter) Rebuild Tethers Around Current Shape, (Shift + Enter) Snapped To {} px",
+                    game_state.quantize_spacing
+                ),
+                "- (Z) Relax Rest Lengths To Current Shape (Shift + Z: Only In Tool)".to_owned(),
+                "- (O) Thin Lattice".to_owned(),
+                "- (Esc) Save Settings and Quit".to_owned(),
+                "- (Alt + 1-4) Tool Preset, (Alt + Shift + 1-4) Save Tool As Preset".to_owned(),
+                format!(
+                    "- (I) Integrator: {}, (Shift + I) Check Timestep, (Ctrl + I) Compare: {}",
+                    game_state.integrator.name(),
+                    if game_state.comparison.is_some() {
+                        "On"
+                    } else {
+                        "Off"
+                    }
+                ),
+                format!(
+                    "- (X) Physics: {}, (U/Shift + U) Iterations: {}",
+                    game_state.physics_mode.name(),
+                    game_state.pbd_iterations
+                ),
+                format!(
+                    "- (\\) Shatter On Impact: {}, (Home/End) Threshold: {:.0} px/s",
+                    if game_state.shatter_enabled {
+                        "On"
+                    } else {
+                        "Off"
+                    },
+                    game_state.shatter_threshold
+                ),
+                format!(
+                    "- (D) Physics Thread: {}, (Shift + D) Sleeping: {}",
+                    if game_state.background_physics {
+                        "Background"
+                    } else {
+                        "Main"
+                    },
+                    if game_state.sleep_enabled {
+                        format!(
+                            "{} asleep below {:.1} px/s",
+                            physics_objects.0.iter().filter(|p| p.sleeping).count(),
+                            game_state.sleep_speed
+                        )
+                    } else {
+                        "Off".to_owned()
+                    }
+                ),
+                format!(
+                    "- (C) Collision: {}, (N/Shift + N) Passes: {}",
+                    game_state.collision_response.name(),
+                    game_state.collision_iterations
+                ),
+                "- (B) Cycle Material Of Body Under Cursor".to_owned(),
+                format!(
+                    "- (T) Inspect Tether Forces, (Shift + T) Tether Count Per Particle: {}",
+                    match game_state.degrees.as_ref().filter(|_| game_state.show_degrees) {
+                        Some((_, degrees)) => format!("On, mostly {}", most_common_degree(degrees)),
+                        None => "Off".to_owned(),
+                    }
+                ),
+                "- (Shift + E) Explode, (Ctrl + E) Implode At Cursor".to_owned(),
+                format!(
+                    "- (E) Kinetic Energy Graph: {}",
+                    if game_state.show_energy_graph {
+                        "On"
+                    } else {
+                        "Off"
+                    }
+                ),
+                format!(
+                    "- (W) Body Centroids: {}",
+                    if game_state.show_body_info {
+                        "On"
+                    } else {
+                        "Off"
+                    }
+                ),
+                format!(
+                    "- (L) Delaunay Overlay: {}, (Shift + L) Tether Crossings: {}",
+                    if game_state.show_delaunay {
+                        "On"
+                    } else {
+                        "Off"
+                    },
+                    match &game_state.tether_crossings {
+                        Some(crossings) => crossings.len().to_string(),
+                        None => "Off".to_owned(),
+                    }
+                ),
+                format!(
+                    "- (S) Displacement Field: {}, (Shift + S) Save It",
+                    if game_state.show_displacement {
+                        "On"
+                    } else {
+                        "Off"
+                    }
+                ),
+                format!(
+                    "- (J) Force Probe: {}",
+                    if game_state.force_probe { "On" } else { "Off" }
+                ),
+                format!(
+                    "- (V) Capture Ghost, (Shift + V) Clear, (Y) Show/Hide: {}",
+                    if game_state.ghost_visible {
+                        "Shown"
+                    } else {
+                        "Hidden"
+                    }
+                ),
+                format!(
+                    "- (Shift + Y) Rest Shape: {}",
+                    if game_state.show_rest_shape {
+                        "Shown"
+                    } else {
+                        "Hidden"
+                    }
+                ),
+                format!(
+                    "- (K) {} Strain Log (Shift + K: Only Tethers In Tool)",
+                    if game_state.strain_log.is_some() {
+                        "Save"
+                    } else {
+                        "Start"
+                    }
+                ),
+                format!(
+                    "- (Ctrl + K) Step Script: {}{}",
+                    game_state.step_script.name(),
+                    if game_state.sim_halted {
+                        " (stopped)"
+                    } else {
+                        ""
+                    }
+                ),
+                format!(
+                    "- (G) Gravity: {} ({:.0}, {:.0}), (Ctrl + Drag) Set Direction",
+                    if game_state.gravity_enabled {
+                        "On"
+                    } else {
+                        "Off"
+                    },
+                    game_state.gravity.x,
+                    game_state.gravity.y
+                ),
+                "- (A) Anchor Top Row, (Shift + A) Release Anchors".to_owned(),
+                format!(
+                    "- (F) Floor: {}, (PgUp/PgDn) Move Floor",
+                    if game_state.floor_enabled {
+                        "On"
+                    } else {
+                        "Off"
+                    }
+                ),
+                "- (,/.) Step Through Particles, (/) Stop, (Alt + Arrows) Nudge".to_owned(),
+                "- (Middle Click) Inspect Particle And Print Its Tethers".to_owned(),
+                "- (F2/F3) Particle Size, (Shift) Circle Segments, (Ctrl) Tether Width".to_owned(),
+                format!(
+                    "- (F1) Anti-Aliasing: {}",
+                    if game_state.anti_aliasing {
+                        "On"
+                    } else {
+                        "Off"
+                    }
+                ),
+                format!(
+                    "- (F4) Background, (F5) Transparent Recording: {}, (Shift + F5) Export Still",
+                    if game_state.transparent_recording {
+                        "On"
+                    } else {
+                        "Off"
+                    }
+                ),
+                format!(
+                    "- (F6) Reload Obstacles From {}: {}",
+                    OBSTACLE_PATH,
+                    game_state.obstacles.len()
+                ),
+                format!(
+                    "- (Shift + F6) Reload Timeline From {}: {} changes, Wind ({:.0}, {:.0})",
+                    TIMELINE_PATH,
+                    game_state.timeline.len(),
+                    game_state.wind.x,
+                    game_state.wind.y
+                ),
+                format!("- (F7/F8) Export/Import Mesh As {}", MESH_PATH),
+                format!(
+                    "- (F9) Adaptive Quality: {}, (Shift + F9) Target: {:.0} fps",
+                    if game_state.adaptive_quality {
+                        game_state.render_quality.name()
+                    } else {
+                        "Off"
+                    },
+                    game_state.target_fps
+                ),
+                "- (0-4) Set Body Group, (Shift + 0-4) Toggle Group Collision".to_owned(),
+                format!(
+                    "- (M/Shift + M) Global Damping: {:.1} /s",
+                    game_state.global_damping
+                ),
+                format!(
+                    "- (-/=) Temperature: {:.0} (stiffness x{:.2})",
+                    game_state.temperature,
+                    game_state.stiffness_scale()
+                ),
+                format!(
+                    "Energy: {:.1}",
+                    total_energy(physics_objects, game_state.stiffness_scale())
+                ),
+                format!(
+                    "Sim Time: {:.3} s, Steps: {}",
+                    game_state.sim_time, game_state.sim_steps
+                ),
+                format!(
+                    "Substeps: {} (avg {:.2}, max {})",
+                    game_state.last_substeps, game_state.average_substeps, game_state.max_substeps
+                ),
+            ];
+            for (i, line) in help_lines.iter().enumerate() {
+                draw_text(
+                    line,
+                    9.,
+                    80. + 20. * i as f32,
+                    18.,
+                    Color::from_rgba(203, 206, 209, 140),
+                );
+            }
 
             draw_text(
-                "Scroll to change tool sizes. [Arrow keys to pan. -- Right click to repulse.]",
+                "Scroll or Tab to change tool sizes. [Arrow keys to pan. -- Right click to repulse.]",
                 8.,
                 h - 8.,
                 23.,
                 Color::from_hex(0x777A84),
             );
 
-            // Draw the force tool
+            // Highlight the inspected tether and graph its force components
+            if let Some(tether) = game_state
+                .selected_tether
+                .and_then(|i| physics_objects.1.get(i))
+            {
+                let p1 = physics_objects.0[tether.p1_index].position;
+                let p2 = physics_objects.0[tether.p2_index].position;
+                draw_line(p1.x, p1.y, p2.x, p2.y, 2., Color::from_hex(0x3DE7B4));
+                render_force_graph(&game_state.force_history, w - 210., 10., 200., 100.);
+            }
+
+            // Graph the recent kinetic energy above the tool HUD
+            if game_state.show_energy_graph {
+                render_energy_graph(&game_state.energy_history, w - 230., h - 210., 220., 100.);
+            }
+
+            // Highlight the inspected particle and list its state
+            if let Some(index) = game_state
+                .inspected_particle
+                .filter(|i| *i < physics_objects.0.len())
+            {
+                render_particle_inspector(physics_objects, index, w - 210., 120.);
+            }
+
+            // Show the particle a new tether would start from
+            if let Some(particle) = game_state
+                .edit_selection
+                .and_then(|i| physics_objects.0.get(i))
+            {
+                let p = particle.position;
+                draw_circle_lines(p.x, p.y, 5., 1.5, Color::from_hex(0x3DE7B4));
+                draw_line(
+                    p.x,
+                    p.y,
+                    mouse_x,
+                    mouse_y,
+                    1.,
+                    Color::from_rgba(61, 231, 180, 120),
+                );
+            }
+
+            // Draw the mouse spring
+            if let Some(particle) = game_state
+                .grabbed_particle
+                .and_then(|i| physics_objects.0.get(i))
+            {
+                // Yellow when slack, red at full tension, with the pull written by the cursor
+                let p = particle.position;
+                let tension =
+                    game_state.mouse_spring_stiffness * (vec2(mouse_x, mouse_y) - p).length();
+                let t = (tension / MOUSE_SPRING_FULL_TENSION).min(1.);
+                let color = Color::new(0.91, 0.71 * (1. - t) + 0.24 * t, 0.24, 1.);
+                draw_line(p.x, p.y, mouse_x, mouse_y, 1. + 2. * t, color);
+                draw_text(
+                    &format!(
+                        "k {:.0}, pull {:.0}",
+                        game_state.mouse_spring_stiffness, tension
+                    ),
+                    mouse_x + 12.,
+                    mouse_y + 20.,
+                    18.,
+                    color,
+                );
+            }
+
+            // Overlay the Delaunay triangulation of the particles
+            if let Some((_, triangles)) = game_state
+                .delaunay
+                .as_ref()
+                .filter(|_| game_state.show_delaunay)
+            {
+                let color = Color::from_rgba(180, 80, 242, 90);
+                for triangle in triangles {
+                    for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+                        let (Some(p1), Some(p2)) = (
+                            physics_objects.0.get(triangle[a]),
+                            physics_objects.0.get(triangle[b]),
+                        ) else {
+                            continue;
+                        };
+                        let (p1, p2) = (p1.position, p2.position);
+                        draw_line(p1.x, p1.y, p2.x, p2.y, 1., color);
+                    }
+                }
+            }
+
+            // Color each particle by how many tethers it has, so badly connected ones stand out
+            if let Some((_, degrees)) = game_state
+                .degrees
+                .as_ref()
+                .filter(|_| game_state.show_degrees)
+            {
+                for (particle, &degree) in physics_objects.0.iter().zip(degrees) {
+                    let color = DEGREE_COLORS[degree.min(DEGREE_COLORS.len() - 1)];
+                    draw_circle(
+                        particle.position.x,
+                        particle.position.y,
+                        game_state.particle_render_radius + 1.,
+                        Color::from_hex(color),
+                    );
+                }
+            }
+
+            // Arrow from each particle's rest position to where it is now, redder the further it moved
+            if game_state.show_displacement {
+                let mut batch = LineBatch::new();
+                for particle in &physics_objects.0 {
+                    let displacement = particle.position - particle.rest_position;
+                    let t = (displacement.length() / DISPLACEMENT_COLOR_SCALE).min(1.);
+                    let color = Color::new(
+                        0.24 + 0.67 * t,
+                        0.61 * (1. - t) + 0.24 * t,
+                        0.91 * (1. - t) + 0.24 * t,
+                        0.8,
+                    );
+                    batch.line(particle.rest_position, particle.position, 1., color);
+                }
+                batch.flush();
+            }
+
+            // Highlight tethers that cross, where a body has folded through itself
+            if let Some(crossings) = &game_state.tether_crossings {
+                let mut batch = LineBatch::new();
+                for &(a, b) in crossings {
+                    for tether in [a, b].into_iter().filter_map(|i| physics_objects.1.get(i)) {
+                        let (Some(p1), Some(p2)) = (
+                            physics_objects.0.get(tether.p1_index),
+                            physics_objects.0.get(tether.p2_index),
+                        ) else {
+                            continue;
+                        };
+                        batch.line(p1.position, p2.position, 2., Color::from_hex(0xE73D3D));
+                    }
+                }
+                batch.flush();
+            }
+
+            // Mark each body's centroid with its motion
+            if game_state.show_body_info {
+                for body in bodies(physics_objects) {
+                    render_body_info(&physics_objects.0, &body);
+                }
+            }
+
+            // Preview the gravity being dragged out
+            if let Some(start) = game_state.gravity_drag_start {
+                let end = vec2(mouse_x, mouse_y);
+                let color = Color::from_hex(0x3D9BE7);
+                render_arrow(start, end, color);
+                let gravity = (end - start) * GRAVITY_DRAG_SCALE;
+                draw_text(
+                    &format!("g ({:.0}, {:.0})", gravity.x, gravity.y),
+                    end.x + 8.,
+                    end.y - 8.,
+                    18.,
+                    color,
+                );
+            }
+
+            // Preview the launch being dragged out
+            if let Some((_, start)) = game_state.launch_start {
+                let end = vec2(mouse_x, mouse_y);
+                let color = Color::from_hex(0x6FE73D);
+                render_arrow(start, end, color);
+                let velocity = (end - start) * LAUNCH_VELOCITY_SCALE;
+                draw_text(
+                    &format!("v ({:.0}, {:.0})", velocity.x, velocity.y),
+                    end.x + 8.,
+                    end.y - 8.,
+                    18.,
+                    color,
+                );
+            }
+
+            // Draw the force probe
+            if game_state.force_probe {
+                let point = vec2(mouse_x, mouse_y);
+                let force = probe_force(
+                    &physics_objects.0,
+                    point,
+                    FORCE_TOOL_STRENGTH * game_state.force_radius,
+                );
+                render_force_arrow(point, force);
+            }
+
+            // Outline where the chosen stamp would be placed
+            if let (SimTool::Stamp, Some((_, stamp))) = (
+                game_state.sim_tool,
+                game_state.stamps.get(game_state.stamp_index),
+            ) {
+                let (stamp_w, stamp_h) = (stamp.width() as f32, stamp.height() as f32);
+                draw_rectangle_lines(
+                    mouse_x - stamp_w / 2.,
+                    mouse_y - stamp_h / 2.,
+                    stamp_w,
+                    stamp_h,
+                    1.,
+                    Color::from_rgba(180, 80, 242, 160),
+                );
+            }
+
+            // Draw the sim tool
             draw_circle_lines(
                 mouse_x,
                 mouse_y,
                 game_state.force_radius,
                 1.,
-                Color::from_hex(0xE73D71),
+                match game_state.sim_tool {
+                    SimTool::Force => Color::from_hex(0xE73D71),
+                    SimTool::Stiffness => Color::from_hex(0x3D9BE7),
+                    SimTool::Edit => Color::from_hex(0x3DE7B4),
+                    SimTool::Spring => Color::from_hex(0xE7B43D),
+                    SimTool::Muscle => Color::from_hex(0xE76F3D),
+                    SimTool::Stamp => Color::from_hex(0xB450F2),
+                    SimTool::Anchor => Color::from_hex(0x9BA3B4),
+                    SimTool::Launch => Color::from_hex(0x6FE73D),
+                },
             )
         }
     }
 }
 
-fn switch_modes(current_mode: Mode) -> Mode {
-    match current_mode {
-        Mode::Create => Mode::Sim,
-        Mode::Sim => Mode::Create,
-    }
+// Render the physics objects, skipping the ones outside of the view.
+fn render_physics_objects(physics_objects: &(Vec<Particle>, Vec<Tether>), style: RenderStyle) {
+    let (view_min, view_max) = view_bounds(style.view, CULL_MARGIN);
+    let mut batch = LineBatch::new();
+    physics_objects
+        .1
+        .iter()
+        .filter(|_| style.show_tethers)
+        .for_each(|tether| {
+            let p1 = physics_objects.0[tether.p1_index].position;
+            let p2 = physics_objects.0[tether.p2_index].position;
+            if segment_intersects_rect(p1, p2, view_min, view_max) {
+                tether.render(
+                    &physics_objects.0,
+                    style.smooth,
+                    style.tether_width,
+                    &mut batch,
+                );
+            }
+        });
+    batch.flush();
+    physics_objects.0.iter().for_each(|particle| {
+        let p = particle.position;
+        if p.cmpge(view_min).all() && p.cmple(view_max).all() {
+            particle.render(style.smooth, style.particle_radius, style.circle_segments);
+        }
+    });
 }
 
-enum DrawMode {
-    Add,
-    Remove,
+// How a drawing is turned into particles and tethers, copied out of GameState.
+struct LatticeSettings {
+    lattice_type: LatticeType,
+    brace_squares: bool,
+    spacing: f32, // Hexagon radius, or square side
+    stiffness: f32,
+    damping: f32,
+    total_mass: Option<f32>, // Spread this mass evenly over each body instead of giving every particle 1.
+    remove_orphans: bool,    // Drop particles that didn't end up with any tethers
+    weld: bool,
 }
 
-struct GameState {
-    draw_mode: DrawMode,
-    was_drawing: bool,
-    last_draw_pos: (f32, f32),
-    add_radius: f32,
-    remove_radius: f32,
-    force_radius: f32,
+// How the physics objects are drawn, traded between quality and speed for large scenes.
+#[derive(Clone, Copy)]
+struct RenderStyle {
+    smooth: bool,
+    particle_radius: f32,
+    circle_segments: u8,
+    tether_width: f32,
+    show_tethers: bool,
+    view: Vec2, // Size of the canvas, anything outside of it isn't drawn
 }
 
-impl GameState {
+// Lines collected into meshes, so thousands of them cost a few geometry calls instead of one draw_line each.
+struct LineBatch {
+    mesh: Mesh,
+}
+
+impl LineBatch {
     fn new() -> Self {
-        GameState {
-            draw_mode: DrawMode::Add,
-            was_drawing: false,
-            last_draw_pos: (0., 0.),
-            add_radius: 5.,
-            remove_radius: 20.,
-            force_radius: 20.,
+        Self {
+            mesh: Mesh {
+                vertices: Vec::with_capacity(4 * LINE_BATCH_QUADS),
+                indices: Vec::with_capacity(6 * LINE_BATCH_QUADS),
+                texture: None,
+            },
         }
     }
-}
-
-// I hate lines.
-fn draw_rounded_line(
-    image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
-    pos1: (f32, f32),
-    pos2: (f32, f32),
-    width: f32,
-    color: Rgba<u8>,
-) {
-    // Draw the end caps
-    imageproc::drawing::draw_filled_circle_mut(
-        image,
-        (pos1.0 as i32, pos1.1 as i32),
-        (width / 2.) as i32,
-        color,
-    );
-    imageproc::drawing::draw_filled_circle_mut(
-        image,
-        (pos2.0 as i32, pos2.1 as i32),
-        (width / 2.) as i32,
-        color,
-    );
 
-    let pos1_vec = vec2(pos1.0, pos1.1);
-    let pos2_vec = vec2(pos2.0, pos2.1);
-    let distance = pos2_vec.distance(pos1_vec);
+    // Queue the same quad draw_line would draw, drawing the batch first if it's full.
+    fn line(&mut self, a: Vec2, b: Vec2, thickness: f32, color: Color) {
+        let offset = (b - a).perp().normalize_or_zero() * thickness / 2.;
+        if offset == Vec2::ZERO {
+            return;
+        }
+        if self.mesh.indices.len() >= 6 * LINE_BATCH_QUADS {
+            self.flush();
+        }
 
-    // Avoid trying to draw an empty polygon
-    if (distance as i32) < 1 {
-        return;
+        let first = self.mesh.vertices.len() as u16;
+        for corner in [a + offset, a - offset, b + offset, b - offset] {
+            self.mesh.vertices.push(macroquad::models::Vertex {
+                position: corner.extend(0.),
+                uv: Vec2::ZERO,
+                color,
+            });
+        }
+        self.mesh
+            .indices
+            .extend([0, 1, 2, 2, 1, 3].map(|i| first + i));
     }
 
-    let line_direction = (pos2_vec - pos1_vec).normalize();
-    let line_perp = line_direction.perp();
-
-    let corner1 = pos1_vec + line_perp * width / 2.;
-    let corner2 = pos1_vec - line_perp * width / 2.;
-    let corner3 = pos2_vec - line_perp * width / 2.;
-    let corner4 = pos2_vec + line_perp * width / 2.;
-
-    imageproc::drawing::draw_polygon_mut(
-        image,
-        &[
-            Point::new(corner1.x as i32, corner1.y as i32),
-            Point::new(corner2.x as i32, corner2.y as i32),
-            Point::new(corner3.x as i32, corner3.y as i32),
-            Point::new(corner4.x as i32, corner4.y as i32),
-        ],
-        color,
-    );
+    // Draw everything queued so far.
+    fn flush(&mut self) {
+        if !self.mesh.indices.is_empty() {
+            draw_mesh(&self.mesh);
+            self.mesh.vertices.clear();
+            self.mesh.indices.clear();
+        }
+    }
 }
 
-// Implementation of the s2sphere flood fill algorithm. https://github.com/qedus/sphere
-fn flood_fill(
-    create_canvas: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
-    start_pos: (u32, u32),
-    fill_color: Rgba<u8>,
-) {
-    let start_color = *create_canvas.get_pixel(start_pos.0, start_pos.1);
-    let w = create_canvas.width();
-    let h = create_canvas.height();
+// Camera drawing canvas coordinates at the scale and screen position.
+fn view_camera((scale, offset): (f32, Vec2)) -> Camera2D {
+    Camera2D::from_display_rect(macroquad::math::Rect::new(
+        -offset.x / scale,
+        -offset.y / scale,
+        screen_width() / scale,
+        screen_height() / scale,
+    ))
+}
 
-    // Prevent infinite loops
-    if start_color == fill_color {
-        return;
-    }
+// Corners of the visible area, expanded by margin on every side.
+fn view_bounds(view: Vec2, margin: f32) -> (Vec2, Vec2) {
+    (vec2(-margin, -margin), view + vec2(margin, margin))
+}
 
-    let mut frontier = vec![start_pos];
+// Whether segments a1-a2 and b1-b2 cross at a point inside both. Touching or collinear segments don't count.
+fn segments_cross(a1: Vec2, a2: Vec2, b1: Vec2, b2: Vec2) -> bool {
+    let side = |p: Vec2, q1: Vec2, q2: Vec2| (q2 - q1).perp_dot(p - q1);
+    side(b1, a1, a2) * side(b2, a1, a2) < 0. && side(a1, b1, b2) * side(a2, b1, b2) < 0.
+}
 
-    // Keep going until algo can't find more unfilled pixels
-    while !frontier.is_empty() {
-        let (x, y) = frontier.pop().unwrap();
-        let this_color = *create_canvas.get_pixel(x, y);
+// Index pairs of tethers that cross each other but didn't in the rest shape (like a braced square's
+// diagonals do), ignoring tethers sharing a particle. Tethers are swept left to right so only ones
+// overlapping horizontally get tested, but a tangled body is still O(n^2).
+fn crossing_tethers(physics_objects: &(Vec<Particle>, Vec<Tether>)) -> Vec<(usize, usize)> {
+    let (particles, tethers) = physics_objects;
+    let ends = |i: usize| {
+        (
+            particles[tethers[i].p1_index].position,
+            particles[tethers[i].p2_index].position,
+        )
+    };
+    let mut order: Vec<usize> = (0..tethers.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (a1, a2) = ends(a);
+        let (b1, b2) = ends(b);
+        a1.x.min(a2.x).total_cmp(&b1.x.min(b2.x))
+    });
 
-        // Skip branching out from this pixel if it is a "border"
-        if this_color != start_color {
-            continue;
+    let mut crossings = vec![];
+    for (n, &a) in order.iter().enumerate() {
+        let (a1, a2) = ends(a);
+        let right = a1.x.max(a2.x);
+        for &b in &order[n + 1..] {
+            let (b1, b2) = ends(b);
+            if b1.x.min(b2.x) > right {
+                break;
+            }
+            let (ta, tb) = (&tethers[a], &tethers[b]);
+            let shared = [tb.p1_index, tb.p2_index].contains(&ta.p1_index)
+                || [tb.p1_index, tb.p2_index].contains(&ta.p2_index);
+            let rest = |tether: &Tether| {
+                (
+                    particles[tether.p1_index].rest_position,
+                    particles[tether.p2_index].rest_position,
+                )
+            };
+            if !shared && segments_cross(a1, a2, b1, b2) {
+                let ((r1, r2), (s1, s2)) = (rest(ta), rest(tb));
+                if !segments_cross(r1, r2, s1, s2) {
+                    crossings.push((a, b));
+                }
+            }
         }
+    }
+    crossings
+}
 
-        // Color the pixel (like infection)
-        create_canvas.draw_pixel(x, y, fill_color);
+// Whether any part of the segment lies in the rectangle (Liang-Barsky clipping).
+fn segment_intersects_rect(a: Vec2, b: Vec2, rect_min: Vec2, rect_max: Vec2) -> bool {
+    let delta = b - a;
+    let mut t_enter = 0_f32;
+    let mut t_exit = 1_f32;
 
-        // Branch out to explore other pixels.
-        // Ensures that boundaries are not exceeded.
-        if x + 1 < w {
-            frontier.push((x + 1, y))
-        }
-        if x > 0 {
-            frontier.push((x - 1, y))
-        }
-        if y + 1 < h {
-            frontier.push((x, y + 1))
-        }
-        if y > 0 {
-            frontier.push((x, y - 1))
+    for (p, q) in [
+        (-delta.x, a.x - rect_min.x),
+        (delta.x, rect_max.x - a.x),
+        (-delta.y, a.y - rect_min.y),
+        (delta.y, rect_max.y - a.y),
+    ] {
+        if p == 0. {
+            // Parallel to this edge, so it must already be on the inside of it
+            if q < 0. {
+                return false;
+            }
+        } else {
+            let t = q / p;
+            if p < 0. {
+                t_enter = t_enter.max(t);
+            } else {
+                t_exit = t_exit.min(t);
+            }
         }
     }
+    t_enter <= t_exit
 }
 
-fn handle_create_logic(
-    game_state: &mut GameState,
-    create_canvas: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
-    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+fn render_particle_inspector(
+    physics_objects: &(Vec<Particle>, Vec<Tether>),
+    index: usize,
+    x: f32,
+    y: f32,
 ) {
-    // Handle brush resizing logic
-    match game_state.draw_mode {
-        DrawMode::Add => {
-            game_state.add_radius = (game_state.add_radius + TOOL_SIZING_FACTOR * mouse_wheel().1)
-                .clamp(MIN_TOOL_RADIUS, MAX_TOOL_RADIUS)
-        }
-        DrawMode::Remove => {
-            game_state.remove_radius = (game_state.remove_radius
-                + TOOL_SIZING_FACTOR * mouse_wheel().1)
-                .clamp(MIN_TOOL_RADIUS, MAX_TOOL_RADIUS)
-        }
-    };
+    let particle = &physics_objects.0[index];
+    draw_circle_lines(
+        particle.position.x,
+        particle.position.y,
+        6.,
+        1.5,
+        Color::from_hex(0x3DE7B4),
+    );
 
-    // Lattice fill
-    if is_key_pressed(KeyCode::Enter) {
-        physics_objects.0.clear();
-        physics_objects.1.clear();
-        create_particle_lattice(create_canvas, physics_objects, 10., 10000., 0.);
+    // Mark each tethered neighbor and list the tethers by index
+    let connections = tether_connections(physics_objects, index);
+    for &(_, other, _, _) in &connections {
+        let p = physics_objects.0[other].position;
+        draw_line(
+            particle.position.x,
+            particle.position.y,
+            p.x,
+            p.y,
+            2.,
+            Color::from_hex(0x3DE7B4),
+        );
+        draw_circle_lines(p.x, p.y, 3., 1., Color::from_hex(0x3DE7B4));
     }
 
-    // Brush switching
-    if is_key_pressed(KeyCode::Q) {
-        game_state.draw_mode = match game_state.draw_mode {
-            DrawMode::Add => DrawMode::Remove,
-            DrawMode::Remove => DrawMode::Add,
-        };
+    let mut lines = vec![
+        format!("Particle {} / {}", index, physics_objects.0.len() - 1),
+        format!(
+            "pos ({:.1}, {:.1})",
+            particle.position.x, particle.position.y
+        ),
+        format!(
+            "vel ({:.1}, {:.1})",
+            particle.velocity.x, particle.velocity.y
+        ),
+        format!("mass {:.2}", particle.mass),
+        format!("material {}", MATERIALS[particle.material].name),
+        format!(
+            "force ({:.1}, {:.1})",
+            particle.net_force.x, particle.net_force.y
+        ),
+        format!("tethers {}", connections.len()),
+    ];
+    lines.extend(connections.iter().map(|(tether, other, length, rest)| {
+        format!("t{} -> p{}  {:.1} / {:.1}", tether, other, length, rest)
+    }));
+
+    draw_rectangle(
+        x,
+        y,
+        200.,
+        8. + 18. * lines.len() as f32,
+        Color::from_rgba(0, 0, 0, 120),
+    );
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(
+            line,
+            x + 4.,
+            y + 18. * (i + 1) as f32,
+            18.,
+            Color::from_rgba(203, 206, 209, 200),
+        );
     }
+}
 
-    // Do flood fill
-    if is_key_pressed(KeyCode::F) {
-        flood_fill(
-            create_canvas,
-            (mouse_position().0 as u32, mouse_position().1 as u32),
-            Rgba(DRAW_COLOR),
+// (tether index, particle on the other end, current length, rest length) of every tether attached to the particle.
+fn tether_connections(
+    physics_objects: &(Vec<Particle>, Vec<Tether>),
+    index: usize,
+) -> Vec<(usize, usize, f32, f32)> {
+    physics_objects
+        .1
+        .iter()
+        .enumerate()
+        .filter_map(|(i, tether)| {
+            let other = if tether.p1_index == index {
+                tether.p2_index
+            } else if tether.p2_index == index {
+                tether.p1_index
+            } else {
+                return None;
+            };
+            let length = physics_objects.0[index]
+                .position
+                .distance(physics_objects.0[other].position);
+            Some((i, other, length, tether.initial_dist))
+        })
+        .collect()
+}
+
+// List the active tool and its live settings, with the panel's bottom-left corner at (x, y).
+fn render_tool_hud(mode: Mode, game_state: &mut GameState, x: f32, y: f32) {
+    let mut lines = match mode {
+        Mode::Create => {
+            let (tool, radius) = match game_state.draw_mode {
+                DrawMode::Add => ("Add Brush", game_state.add_radius),
+                DrawMode::Remove => ("Remove Brush", game_state.remove_radius),
+            };
+            vec![
+                format!("Tool: {}", tool),
+                format!("Radius: {:.1}", radius),
+                format!("Layer: {}", game_state.active_layer + 1),
+                format!("Material: {}", MATERIALS[game_state.draw_material].name),
+            ]
+        }
+        Mode::Sim => {
+            let radius = game_state.force_radius;
+            let strength = match game_state.sim_tool {
+                SimTool::Force => format!("{:.0}", FORCE_TOOL_STRENGTH * radius),
+                SimTool::Stiffness => format!("x{:.2} per frame", STIFFNESS_PAINT_STEP),
+                SimTool::Edit => format!("k {:.0}", game_state.tether_stiffness),
+                SimTool::Spring => format!("{:.0}", game_state.mouse_spring_stiffness),
+                SimTool::Muscle => format!(
+                    "{:.2} at {:.2} Hz, phase {:.2}",
+                    game_state.muscle.amplitude,
+                    game_state.muscle.frequency,
+                    game_state.muscle.phase
+                ),
+                SimTool::Stamp => match game_state.stamps.get(game_state.stamp_index) {
+                    Some((name, _)) => name.clone(),
+                    None => "none saved".to_owned(),
+                },
+                SimTool::Anchor => "anchors, Shift releases".to_owned(),
+                SimTool::Launch => format!("{:.1} px/s per px dragged", LAUNCH_VELOCITY_SCALE),
+            };
+            vec![
+                format!("Tool: {}", game_state.sim_tool.label()),
+                format!("Radius: {:.1}", radius),
+                format!("Strength: {}", strength),
+            ]
+        }
+    };
+    if let Some(slot) = game_state.active_tool_preset(mode) {
+        if let Some(preset) = &game_state.tool_presets[slot] {
+            lines.push(format!("Preset {}: {}", slot + 1, preset.name));
+        }
+    }
+
+    let height = 8. + 18. * lines.len() as f32;
+    draw_rectangle(x, y - height, 220., height, Color::from_rgba(0, 0, 0, 120));
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(
+            line,
+            x + 4.,
+            y - height + 18. * (i + 1) as f32,
+            18.,
+            Color::from_rgba(203, 206, 209, 200),
         );
     }
+}
 
-    // Handle clear request
-    if is_key_pressed(KeyCode::Backspace) {
-        physics_objects.0.clear();
-        physics_objects.1.clear();
-        imageproc::drawing::draw_filled_rect_mut(
-            create_canvas,
-            Rect::at(0, 0).of_size(create_canvas.width(), create_canvas.height()),
-            Rgba([0, 0, 0, 0]),
+// Row of material swatches numbered by their shortcut, the active one outlined.
+fn render_material_palette(active: usize, x: f32, y: f32) {
+    const SWATCH_SIZE: f32 = 22.;
+    for (i, material) in MATERIALS.iter().enumerate() {
+        let swatch_x = x + i as f32 * (SWATCH_SIZE + 6.);
+        let [r, g, b, a] = material.color;
+        draw_rectangle(
+            swatch_x,
+            y,
+            SWATCH_SIZE,
+            SWATCH_SIZE,
+            Color::from_rgba(r, g, b, a),
+        );
+        if i == active {
+            draw_rectangle_lines(
+                swatch_x - 2.,
+                y - 2.,
+                SWATCH_SIZE + 4.,
+                SWATCH_SIZE + 4.,
+                2.,
+                Color::from_rgba(237, 229, 76, 235),
+            );
+        }
+        draw_text(
+            &(i + 1).to_string(),
+            swatch_x + 7.,
+            y + 16.,
+            18.,
+            Color::from_rgba(235, 235, 235, 220),
         );
     }
+}
 
-    // Handle drawing logic
-    if is_mouse_button_down(MouseButton::Left) {
-        if game_state.was_drawing {
-            let last_pos = game_state.last_draw_pos;
-            let new_pos = mouse_position();
+// Mass-weighted centroid and total momentum of the particles in the body.
+fn body_motion(particles: &[Particle], body: &[usize]) -> (Vec2, Vec2, f32) {
+    let mass: f32 = body.iter().map(|&i| particles[i].mass).sum();
+    let (weighted_position, momentum) =
+        body.iter()
+            .fold((Vec2::ZERO, Vec2::ZERO), |(position, momentum), &i| {
+                let particle = &particles[i];
+                (
+                    position + particle.mass * particle.position,
+                    momentum + particle.mass * particle.velocity,
+                )
+            });
+    (weighted_position / mass, momentum, mass)
+}
 
-            let draw_info = match game_state.draw_mode {
-                DrawMode::Add => (game_state.add_radius, Rgba(DRAW_COLOR)),
-                DrawMode::Remove => (game_state.remove_radius, Rgba([0, 0, 0, 0])),
-            };
+// Cross at the body's centroid labeled with its average velocity and momentum.
+fn render_body_info(particles: &[Particle], body: &[usize]) {
+    let (centroid, momentum, mass) = body_motion(particles, body);
+    let velocity = momentum / mass;
+    let color = Color::from_hex(0x3DE7B4);
+    draw_line(
+        centroid.x - 5.,
+        centroid.y,
+        centroid.x + 5.,
+        centroid.y,
+        1.5,
+        color,
+    );
+    draw_line(
+        centroid.x,
+        centroid.y - 5.,
+        centroid.x,
+        centroid.y + 5.,
+        1.5,
+        color,
+    );
+    draw_text(
+        &format!(
+            "v ({:.1}, {:.1}) |p| {:.0}",
+            velocity.x,
+            velocity.y,
+            momentum.length()
+        ),
+        centroid.x + 8.,
+        centroid.y - 8.,
+        16.,
+        color,
+    );
+}
 
-            draw_rounded_line(
-                create_canvas,
-                last_pos,
-                new_pos,
-                2. * draw_info.0,
-                draw_info.1,
-            );
-        }
-        game_state.was_drawing = true;
-        // Update last position that was drawn to. (for filling gaps between mouse jumps)
-        game_state.last_draw_pos = mouse_position();
+// Arrow from the point along the force, with length growing logarithmically with its magnitude.
+fn render_force_arrow(point: Vec2, force: Vec2) {
+    let magnitude = force.length();
+    let color = Color::from_hex(0xE7B43D);
+    draw_text(
+        &format!("{:.0}", magnitude),
+        point.x + 8.,
+        point.y - 8.,
+        18.,
+        color,
+    );
+    if magnitude == 0. {
+        return;
     }
-    if is_mouse_button_released(MouseButton::Left) {
-        game_state.was_drawing = false;
+
+    let direction = force / magnitude;
+    render_arrow(
+        point,
+        point + direction * (10. * magnitude.ln_1p()).min(150.),
+        color,
+    );
+}
+
+// Straight arrow with its head at the end point.
+fn render_arrow(from: Vec2, to: Vec2, color: Color) {
+    draw_line(from.x, from.y, to.x, to.y, 2., color);
+    let direction = (to - from).normalize_or_zero();
+    for side in [-1., 1.] {
+        let head = to - 8. * direction + 4. * side * direction.perp();
+        draw_line(to.x, to.y, head.x, head.y, 2., color);
     }
 }
 
-fn handle_sim_logic(
-    game_state: &mut GameState,
-    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
-) {
-    let (mouse_x, mouse_y) = mouse_position();
+// Plot the recorded kinetic energy from zero up to its largest value, newest on the right.
+fn render_energy_graph(history: &VecDeque<f32>, x: f32, y: f32, w: f32, h: f32) {
+    draw_rectangle(x, y, w, h, Color::from_rgba(0, 0, 0, 120));
 
-    // Force tool resizing
-    game_state.force_radius = (game_state.force_radius + TOOL_SIZING_FACTOR * mouse_wheel().1)
-        .clamp(MIN_TOOL_RADIUS, MAX_TOOL_RADIUS);
+    let max_energy = history.iter().copied().fold(1e-6, f32::max);
+    let step = w / ENERGY_HISTORY_LEN as f32;
+    let start = x + w - history.len() as f32 * step;
+    let to_y = |energy: f32| y + h - energy / max_energy * h;
 
-    // Force tool forcing ig
-    if is_mouse_button_down(MouseButton::Left) {
-        apply_force_from_point(
-            physics_objects,
-            vec2(mouse_x, mouse_y),
-            10000. * game_state.force_radius,
+    for (i, (prev, next)) in history.iter().zip(history.iter().skip(1)).enumerate() {
+        let x1 = start + i as f32 * step;
+        draw_line(
+            x1,
+            to_y(*prev),
+            x1 + step,
+            to_y(*next),
+            1.,
+            Color::from_hex(0xE73D71),
         );
     }
-    if is_mouse_button_down(MouseButton::Right) {
-        apply_force_from_point(
-            physics_objects,
-            vec2(mouse_x, mouse_y),
-            -10000. * game_state.force_radius,
+
+    draw_text(
+        &format!("KE max {:.0}", max_energy),
+        x + 4.,
+        y + 14.,
+        16.,
+        Color::from_rgba(203, 206, 209, 200),
+    );
+    if let Some(latest) = history.back() {
+        draw_text(
+            &format!("now {:.0}", latest),
+            x + 4.,
+            y + h - 6.,
+            16.,
+            Color::from_rgba(203, 206, 209, 200),
+        );
+    }
+}
+
+// Plot the linear and correction parts of a tether's force over the recorded frames.
+fn render_force_graph(history: &VecDeque<(f32, f32)>, x: f32, y: f32, w: f32, h: f32) {
+    draw_rectangle(x, y, w, h, Color::from_rgba(0, 0, 0, 120));
+    draw_line(
+        x,
+        y + h / 2.,
+        x + w,
+        y + h / 2.,
+        1.,
+        Color::from_hex(0x777A84),
+    );
+
+    // Scale so the largest recorded magnitude fits in the box
+    let max_force = history
+        .iter()
+        .map(|(linear, correction)| linear.abs().max(correction.abs()))
+        .fold(1e-6, f32::max);
+    let step = w / FORCE_HISTORY_LEN as f32;
+    let to_y = |force: f32| y + h / 2. - force / max_force * h / 2.;
+
+    for (i, (prev, next)) in history.iter().zip(history.iter().skip(1)).enumerate() {
+        let x1 = x + i as f32 * step;
+        let x2 = x1 + step;
+        draw_line(
+            x1,
+            to_y(prev.0),
+            x2,
+            to_y(next.0),
+            1.,
+            Color::from_hex(0x3DE7B4),
+        );
+        draw_line(
+            x1,
+            to_y(prev.1),
+            x2,
+            to_y(next.1),
+            1.,
+            Color::from_hex(0xE7B43D),
         );
     }
 
-    update_physics(physics_objects, get_frame_time());
+    draw_text("linear", x + 4., y + 14., 16., Color::from_hex(0x3DE7B4));
+    draw_text(
+        "correction",
+        x + 4.,
+        y + h - 4.,
+        16.,
+        Color::from_hex(0xE7B43D),
+    );
+    draw_text(
+        &format!("max {:.1}", max_force),
+        x + w - 70.,
+        y + 14.,
+        16.,
+        Color::from_rgba(203, 206, 209, 140),
+    );
 }
 
-fn apply_force_from_point(
-    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
-    point: Vec2,
-    strength: f32,
-) {
-    physics_objects.0.iter_mut().for_each(|particle| {
-        let distance = (particle.position - point).length();
-        let direction = (particle.position - point).normalize();
-        particle.apply_force(strength * direction / distance.powi(2));
-    });
+fn switch_modes(current_mode: Mode) -> Mode {
+    match current_mode {
+        Mode::Create => Mode::Sim,
+        Mode::Sim => Mode::Create,
+    }
 }
 
-fn create_particle_lattice(
-    create_canvas: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
-    hex_radius: f32,
-    stiffness: f32,
-    damping_constant: f32,
-) {
-    // Get a vector of valid centerpoints for hexagons in the lattice.
-    let dx = hex_radius * 3.;
-    let dy = hex_radius * 3.0_f32.sqrt() / 2.;
-    let count_x = ((create_canvas.width() as f32 - 1.) / dx) as u32;
-    let count_y = ((create_canvas.height() as f32 - 1.) / dy) as u32;
+#[derive(Clone, Copy, PartialEq)]
+enum DrawMode {
+    Add,
+    Remove,
+}
 
-    // Create grid of slots that may or may not be hexagons
-    let mut hex_points: Vec<Option<(f32, f32)>> = vec![None; (count_x * count_y) as usize];
+#[derive(Clone, Copy)]
+enum FillPattern {
+    Solid,
+    Checkerboard, // Alternating filled and empty squares
+    Hatch,        // Diagonal filled and empty stripes
+}
 
-    // Fill slots with hexagons with their location in tuple form
-    for row_i in 0..count_y {
-        let left_pad = (3. / 2.) * hex_radius * (row_i % 2) as f32;
-        for column_i in 0..count_x {
-            let x = left_pad + dx * column_i as f32;
-            let y = dy * row_i as f32;
+impl FillPattern {
+    fn name(&self) -> &'static str {
+        match self {
+            FillPattern::Solid => "Solid",
+            FillPattern::Checkerboard => "Checkerboard",
+            FillPattern::Hatch => "Hatch",
+        }
+    }
 
-            if create_canvas.get_pixel(x as u32, y as u32).0 == DRAW_COLOR {
-                hex_points[(row_i * count_x + column_i) as usize] = Some((x, y));
+    // Whether the pixel gets the fill color, rather than being left as a hole.
+    fn covers(&self, x: u32, y: u32) -> bool {
+        match self {
+            FillPattern::Solid => true,
+            FillPattern::Checkerboard => {
+                (x / FILL_PATTERN_SIZE + y / FILL_PATTERN_SIZE).is_multiple_of(2)
             }
+            FillPattern::Hatch => (x + y) % (2 * FILL_PATTERN_SIZE) < FILL_PATTERN_SIZE,
         }
     }
+}
 
-    // Create particles for each hexagon vertex, avoiding duplicate particles
+#[derive(Clone, Copy, PartialEq)]
+enum SimTool {
+    Force,
+    Stiffness,
+    Edit,
+    Spring,
+    Muscle,
+    Stamp,
+    Anchor,
+    Launch, // Drag an arrow from a body to set its velocity
+}
 
-    // indices to topleft-topright-midright-bottomright-bottomleft-midleft particles for every placed hex
-    let mut hex_particles_indices: Vec<Option<[usize; 6]>> =
-        vec![None; (count_x * count_y) as usize];
+impl SimTool {
+    fn name(&self) -> &'static str {
+        match self {
+            SimTool::Force => "Force",
+            SimTool::Stiffness => "Stiffness",
+            SimTool::Edit => "Edit Tethers (Left Click Links, Right Click Deletes)",
+            SimTool::Spring => "Mouse Spring ([/] or Scroll While Holding: Stiffness)",
+            SimTool::Muscle => "Muscles ([/] Amplitude, ;/' Frequency, P Phase)",
+            SimTool::Stamp => "Stamp ([/] Choose, Left Click Places)",
+            SimTool::Anchor => "Anchor Brush (Drag Anchors, Shift/Right Drag Releases)",
+            SimTool::Launch => "Launch (Drag From A Body To Set Its Velocity, Right Click Cancels)",
+        }
+    }
 
-    let cos60 = 1. / 2.;
-    let sin60 = 3.0_f32.sqrt() / 2.;
+    // Short name for the HUD and presets.
+    fn label(&self) -> &'static str {
+        match self {
+            SimTool::Force => "Force",
+            SimTool::Stiffness => "Stiffness",
+            SimTool::Edit => "Edit Tethers",
+            SimTool::Spring => "Mouse Spring",
+            SimTool::Muscle => "Muscles",
+            SimTool::Stamp => "Stamp",
+            SimTool::Anchor => "Anchor Brush",
+            SimTool::Launch => "Launch",
+        }
+    }
+}
 
-    hex_points.iter().enumerate().for_each(|(i, hex_point)| {
-        if hex_point.is_none() {
-            return;
-        } // Disregard if no hex in this spot
-        let (x, y) = hex_point.unwrap();
+#[derive(Clone, Copy, PartialEq)]
+enum PresetTool {
+    Draw(DrawMode),
+    Sim(SimTool),
+}
 
-        // Index of the hex to the top-left of this hex
-        let left_hex_index = match ((i as f32) / (count_x as f32)).floor() as i32 % 2 == 0 {
-            true => {
-                if i as u32 >= count_x + 1 {
-                    i - (count_x + 1) as usize
-                } else {
-                    usize::MAX
-                }
-            }
-            false => {
-                if i as u32 >= count_x {
-                    i - count_x as usize
-                } else {
-                    usize::MAX
-                }
-            }
-        };
-        // Index of the hex to the top-right of this hex
-        let right_hex_index = match ((i as f32) / (count_x as f32)).floor() as i32 % 2 == 0 {
-            true => {
-                if i as u32 >= count_x {
-                    i - count_x as usize
-                } else {
-                    usize::MAX
-                }
-            }
-            false => {
-                if i as u32 >= count_x - 1 {
-                    i - (count_x - 1) as usize
-                } else {
-                    usize::MAX
-                }
-            }
-        };
-        // Index of the hex to the top of this hex
-        let top_hex_index = if i as u32 >= 2 * count_x {
-            i - 2 * count_x as usize
-        } else {
-            usize::MAX
-        };
+impl PresetTool {
+    const ALL: [PresetTool; 10] = [
+        PresetTool::Draw(DrawMode::Add),
+        PresetTool::Draw(DrawMode::Remove),
+        PresetTool::Sim(SimTool::Force),
+        PresetTool::Sim(SimTool::Stiffness),
+        PresetTool::Sim(SimTool::Edit),
+        PresetTool::Sim(SimTool::Spring),
+        PresetTool::Sim(SimTool::Muscle),
+        PresetTool::Sim(SimTool::Stamp),
+        PresetTool::Sim(SimTool::Anchor),
+        PresetTool::Sim(SimTool::Launch),
+    ];
 
-        // Is a hex to the left-top
-        let is_left = if left_hex_index != usize::MAX {
-            hex_points[left_hex_index].is_some()
-        } else {
-            false
-        };
-        // Is a hex to the right-top
-        let is_right = if right_hex_index != usize::MAX {
-            hex_points[right_hex_index].is_some()
-        } else {
-            false
-        };
-        // Is a hex above
-        let is_top = if top_hex_index != usize::MAX {
-            hex_points[top_hex_index].is_some()
-        } else {
-            false
-        };
+    fn name(&self) -> &'static str {
+        match self {
+            PresetTool::Draw(DrawMode::Add) => "Add Brush",
+            PresetTool::Draw(DrawMode::Remove) => "Remove Brush",
+            PresetTool::Sim(tool) => tool.label(),
+        }
+    }
 
-        // // indexes to topleft-topright-midright-bottomright-bottomleft-midleft particles for this hex
-        // let mut hex_particle_indices: [Option<usize>; 6] = [None; 6];
+    fn mode(&self) -> Mode {
+        match self {
+            PresetTool::Draw(_) => Mode::Create,
+            PresetTool::Sim(_) => Mode::Sim,
+        }
+    }
+}
 
-        // indices to topleft-topright-midright-bottomright-bottomleft-midleft particles for this hex
-        let mut particle_indices: [usize; 6] = [0; 6];
+// A tool and its settings saved to a number key, named after the tool unless renamed in the config.
+#[derive(Clone)]
+struct ToolPreset {
+    name: String,
+    tool: PresetTool,
+    radius: f32,
+    strength: Option<f32>, // Spring stiffness or muscle amplitude, for the tools that have one
+}
 
-        // Place these if they haven't been placed in prior iteration
-        if !is_left && !is_top {
-            let top_left = vec2(x - hex_radius * cos60, y - hex_radius * sin60);
-            physics_objects
-                .0
-                .push(Particle::new(top_left, Vec2::ZERO, 1.));
-            particle_indices[0] = physics_objects.0.len() - 1;
-        }
-        if !is_right && !is_top {
-            let top_right = vec2(x + hex_radius * cos60, y - hex_radius * sin60);
-            physics_objects
-                .0
-                .push(Particle::new(top_right, Vec2::ZERO, 1.));
-            particle_indices[1] = physics_objects.0.len() - 1;
-        }
-        if !is_left {
-            let mid_left = vec2(x - hex_radius, y);
-            physics_objects
-                .0
-                .push(Particle::new(mid_left, Vec2::ZERO, 1.));
-            particle_indices[5] = physics_objects.0.len() - 1;
-        }
-        if !is_right {
-            let mid_right = vec2(x + hex_radius, y);
-            physics_objects
-                .0
-                .push(Particle::new(mid_right, Vec2::ZERO, 1.));
-            particle_indices[2] = physics_objects.0.len() - 1;
-        }
+#[derive(Clone, Copy)]
+enum Integrator {
+    SemiImplicitEuler,
+    Leapfrog,
+}
 
-        // Get the indices of particles from hexagons that placed them first
-        if is_left {
+// How a particle behaves on contact, and the color it is drawn with in the canvas.
+struct Material {
+    name: &'static str,
+    color: [u8; 4],
+    restitution: f32, // Fraction of approach speed kept after a collision
+    friction: f32,    // Fraction of sliding speed lost on each contact
+}
+
+// Index of the material drawn with this pixel's color, if any.
+fn material_at(pixel: &Rgba<u8>) -> Option<usize> {
+    MATERIALS
+        .iter()
+        .position(|material| material.color == pixel.0)
+}
+
+// Whether the pixel is part of the drawing, in any material.
+fn is_drawn(pixel: &Rgba<u8>) -> bool {
+    material_at(pixel).is_some()
+}
+
+// Snap a pixel back to drawn/empty, drawn in the material whose color is closest.
+fn snap_pixel(pixel: Rgba<u8>) -> Rgba<u8> {
+    if pixel.0[3] < 128 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let distance = |color: [u8; 4]| -> i32 {
+        (0..3)
+            .map(|i| (color[i] as i32 - pixel.0[i] as i32).pow(2))
+            .sum()
+    };
+    let material = MATERIALS
+        .iter()
+        .min_by_key(|material| distance(material.color))
+        .unwrap();
+    Rgba(material.color)
+}
+
+// Like snap_pixel, for a pixel blurred or resampled together with empty ones.
+// Empty pixels are transparent black, so the color is scaled back up by the coverage first.
+fn snap_filtered_pixel(pixel: Rgba<u8>) -> Rgba<u8> {
+    let coverage = pixel.0[3] as f32 / 255.;
+    let unblend = |channel: u8| (channel as f32 / coverage).min(255.) as u8;
+    let [r, g, b, a] = pixel.0;
+    snap_pixel(Rgba([unblend(r), unblend(g), unblend(b), a]))
+}
+
+#[derive(Clone, Copy)]
+enum PhysicsMode {
+    ForceBased, // Tethers are springs that apply forces
+    Pbd,        // Tethers are distance constraints projected after integration
+}
+
+impl PhysicsMode {
+    fn name(&self) -> &'static str {
+        match self {
+            PhysicsMode::ForceBased => "Force-Based",
+            PhysicsMode::Pbd => "Position-Based",
+        }
+    }
+}
+
+// Levels adaptive quality steps through, each cheaper to draw than the last.
+#[derive(Clone, Copy, PartialEq)]
+enum RenderQuality {
+    Full,
+    Reduced, // No anti-aliasing and coarse circles
+    Minimal, // Particles only, with as few sides as possible
+}
+
+impl RenderQuality {
+    fn name(&self) -> &'static str {
+        match self {
+            RenderQuality::Full => "Full",
+            RenderQuality::Reduced => "Reduced",
+            RenderQuality::Minimal => "Minimal",
+        }
+    }
+
+    fn lower(&self) -> Option<RenderQuality> {
+        match self {
+            RenderQuality::Full => Some(RenderQuality::Reduced),
+            RenderQuality::Reduced => Some(RenderQuality::Minimal),
+            RenderQuality::Minimal => None,
+        }
+    }
+
+    fn higher(&self) -> Option<RenderQuality> {
+        match self {
+            RenderQuality::Full => None,
+            RenderQuality::Reduced => Some(RenderQuality::Full),
+            RenderQuality::Minimal => Some(RenderQuality::Reduced),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum LatticeType {
+    Hexagonal,
+    Square, // Square cells, optionally braced across both diagonals
+}
+
+impl LatticeType {
+    fn name(&self) -> &'static str {
+        match self {
+            LatticeType::Hexagonal => "Hexagonal",
+            LatticeType::Square => "Square",
+        }
+    }
+
+    // Tethers on a particle inside the lattice. Bracing adds one diagonal from each of a corner's four cells.
+    fn interior_degree(&self, braced: bool) -> usize {
+        match self {
+            LatticeType::Hexagonal => 3,
+            LatticeType::Square if braced => 8,
+            LatticeType::Square => 4,
+        }
+    }
+
+    // Particles per square pixel of drawing. A honeycomb has two corners per hexagon.
+    fn particle_density(&self, spacing: f32) -> f32 {
+        match self {
+            LatticeType::Hexagonal => 4. / (3. * 3.0_f32.sqrt() * spacing * spacing),
+            LatticeType::Square => 1. / (spacing * spacing),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum CollisionResponse {
+    Penalty,
+    Impulse,
+}
+
+impl CollisionResponse {
+    fn name(&self) -> &'static str {
+        match self {
+            CollisionResponse::Penalty => "Penalty",
+            CollisionResponse::Impulse => "Impulse",
+        }
+    }
+}
+
+impl Integrator {
+    fn other(&self) -> Self {
+        match self {
+            Integrator::SemiImplicitEuler => Integrator::Leapfrog,
+            Integrator::Leapfrog => Integrator::SemiImplicitEuler,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Integrator::SemiImplicitEuler => "Semi-Implicit Euler",
+            Integrator::Leapfrog => "Leapfrog",
+        }
+    }
+}
+
+// A separately drawn image, composited with the others before building or displaying.
+struct Layer {
+    canvas: RgbaImage,
+    visible: bool,
+}
+
+impl Layer {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            canvas: RgbaImage::new(width, height),
+            visible: true,
+        }
+    }
+}
+
+struct GameState {
+    layers: Vec<Layer>,            // Drawing layers from bottom to top
+    canvas_resolution: (u32, u32), // Configured canvas size, 0 for the window's size
+    canvas_dirty: bool, // Whether the layers changed since the canvas texture was last uploaded
+    canvas_empty: bool, // No layer has a drawn pixel, refreshed along with the canvas texture
+    scratch_canvas: RgbaImage, // Destination for image operations on a layer, swapped in when done
+    active_layer: usize,
+    draw_material: usize, // Index into MATERIALS painted by the Add brush and fills
+    radius_entry: Option<String>, // Digits typed so far while entering an exact tool radius
+    fill_pattern: FillPattern,
+    show_grid: bool,
+    snap_to_grid: bool, // Round the brush position to the nearest grid point
+    grid_spacing: f32,
+    draw_mode: DrawMode,
+    was_drawing: bool,
+    last_draw_pos: (f32, f32),
+    stroke_start: (f32, f32), // Where the current stroke began, for straight-line strokes
+    add_radius: f32,
+    remove_radius: f32,
+    normalize_mass: bool,
+    remove_orphans: bool,
+    weld_duplicates: bool,         // Merge coincident particles after building
+    build_from_empty: bool,        // Lattice the undrawn space instead of the drawing
+    built_area: Option<RgbaImage>, // Everything the world has been built from, appending skips it
+    lattice_type: LatticeType,
+    brace_squares: bool,   // Add diagonal tethers across square lattice cells
+    tether_stiffness: f32, // Given to newly built or linked tethers
+    tether_damping: f32,
+    global_damping: f32, // Rate every particle's velocity decays at, per second
+    force_radius: f32,
+    sim_tool: SimTool,
+    edit_selection: Option<usize>, // First particle clicked when linking two with a tether
+    grabbed_particle: Option<usize>, // Particle pulled toward the cursor by the mouse spring
+    mouse_spring_stiffness: f32,
+    tool_presets: [Option<ToolPreset>; TOOL_PRESET_COUNT],
+    muscle: Actuator, // Settings given to tethers turned into muscles
+    stamps: Vec<(String, RgbaImage)>, // Saved drawings that can be placed as new bodies, by file name
+    stamp_index: usize,
+    anchor_stroke_end: Option<Vec2>, // Where the anchor brush was last frame, while it's being dragged
+    launch_start: Option<(usize, Vec2)>, // Particle a launch arrow was started on, and where
+    integrator: Integrator,
+    physics_mode: PhysicsMode,
+    pbd_iterations: u32,
+    collision_response: CollisionResponse,
+    collision_iterations: u32,
+    gravity_enabled: bool,
+    gravity: Vec2,                    // Acceleration in pixels per second squared
+    wind: Vec2,                       // Velocity of the air, in pixels per second
+    timeline: Vec<TimelineEvent>,     // Scheduled setting changes, in order of time
+    timeline_time: f64,               // Sim time the timeline has been run up to
+    comparison: Option<Comparison>,   // Same world run with the other integrator, drawn alongside
+    gravity_drag_start: Option<Vec2>, // Where a drag setting the gravity began
+    temperature: f32,
+    floor_enabled: bool,
+    floor_y: f32,
+    obstacles: Arc<Vec<Vec<Vec2>>>, // Static polygons particles collide with, shared with the physics thread
+    group_collisions: [[bool; COLLISION_GROUP_COLORS.len()]; COLLISION_GROUP_COLORS.len()], // Which groups collide
+    substep_strain_threshold: f32,
+    max_substeps: u32,
+    max_dt: f32, // Frame time is clamped to this before being split into substeps
+    last_substeps: u32,
+    sim_time: f64,             // Simulated seconds since the lattice was built
+    sim_steps: u64,            // Physics steps since the lattice was built
+    average_substeps: f32,     // Moving average of substeps per frame
+    spatial_grid: SpatialGrid, // Particle positions as of the end of the last physics step
+    background_physics: bool,  // Step the physics on its own thread while the frame is drawn
+    sleep_enabled: bool,       // Stop integrating particles that have settled
+    sleep_speed: f32,
+    quantize_spacing: f32, // Grid the particles are snapped to when rebuilding tethers with Shift
+    physics_worker: Option<PhysicsWorker>,
+    pending_step: Option<(f32, u32)>, // (dt, substeps) to hand to the physics thread after drawing
+    shatter_enabled: bool,            // Break up regions of bodies that take a hard hit
+    shatter_threshold: f32,
+    ghost: Option<Vec<Vec2>>, // Particle positions captured for comparing against the live simulation
+    ghost_visible: bool,
+    force_probe: bool, // Show the net push the force tool would give the bodies at the cursor
+    show_delaunay: bool,
+    show_displacement: bool, // Draw each particle's displacement from its rest position
+    show_rest_shape: bool,   // Draw the tethers between rest positions behind the live objects
+    show_degrees: bool,      // Color particles by their tether count
+    degrees: Option<(u64, Vec<usize>)>, // Tether counts, and the structure version they're for
+    structure_version: u64,  // Bumped whenever particles or tethers are added, removed or replaced
+    tether_crossings: Option<Vec<(usize, usize)>>, // Pairs of crossing tethers, None while not checked
+    show_body_info: bool, // Mark each body's centroid with its average velocity
+    delaunay: Option<(Vec<Vec2>, Vec<[usize; 3]>)>, // Positions the triangulation was made from, and its triangles
+    selected_tether: Option<usize>,
+    inspected_particle: Option<usize>,
+    force_history: VecDeque<(f32, f32)>, // (linear, correction) force of the selected tether per frame
+    show_energy_graph: bool,
+    energy_history: VecDeque<f32>, // Total kinetic energy per frame
+    tool_sizing_factor: f32,
+    notice: Option<(String, f64)>, // Briefly shown message and the time it was posted
+    recording: Option<Recording>,
+    strain_log: Option<StrainLog>,
+    step_script: StepScript,
+    sim_halted: bool, // The step script stopped the simulation
+    show_ui: bool,
+    background_color: u32,
+    transparent_recording: bool,
+    anti_aliasing: bool,
+    particle_render_radius: f32,
+    circle_segments: u8,
+    tether_width: f32,
+    adaptive_quality: bool, // Lower the render quality while frames take longer than the target allows
+    target_fps: f32,
+    render_quality: RenderQuality,
+    average_render_time: f32, // Moving average of the seconds spent on input and drawing per frame
+    quality_changed_at: f64,  // When the render quality last changed, so it holds for a while
+}
+
+impl GameState {
+    fn new() -> Self {
+        GameState {
+            layers: (0..LAYER_COUNT)
+                .map(|_| Layer::new(screen_width() as u32, screen_height() as u32))
+                .collect(),
+            canvas_resolution: (0, 0),
+            canvas_dirty: true,
+            canvas_empty: true,
+            scratch_canvas: RgbaImage::new(screen_width() as u32, screen_height() as u32),
+            active_layer: 0,
+            draw_material: 0,
+            radius_entry: None,
+            fill_pattern: FillPattern::Solid,
+            show_grid: false,
+            snap_to_grid: false,
+            grid_spacing: GRID_SPACING,
+            draw_mode: DrawMode::Add,
+            was_drawing: false,
+            last_draw_pos: (0., 0.),
+            stroke_start: (0., 0.),
+            add_radius: 5.,
+            remove_radius: 20.,
+            normalize_mass: false,
+            remove_orphans: true,
+            weld_duplicates: true,
+            build_from_empty: false,
+            built_area: None,
+            lattice_type: LatticeType::Hexagonal,
+            brace_squares: true,
+            tether_stiffness: TETHER_STIFFNESS,
+            tether_damping: 0.,
+            global_damping: 0.,
+            force_radius: 20.,
+            sim_tool: SimTool::Force,
+            edit_selection: None,
+            grabbed_particle: None,
+            mouse_spring_stiffness: MOUSE_SPRING_STIFFNESS,
+            tool_presets: Default::default(),
+            muscle: Actuator {
+                rest_length: 0.,
+                amplitude: MUSCLE_AMPLITUDE,
+                frequency: MUSCLE_FREQUENCY,
+                phase: 0.,
+            },
+            stamps: vec![],
+            stamp_index: 0,
+            anchor_stroke_end: None,
+            launch_start: None,
+            integrator: Integrator::SemiImplicitEuler,
+            physics_mode: PhysicsMode::ForceBased,
+            pbd_iterations: PBD_ITERATIONS,
+            collision_response: CollisionResponse::Penalty,
+            collision_iterations: COLLISION_ITERATIONS,
+            gravity_enabled: false,
+            gravity: vec2(0., GRAVITY),
+            wind: Vec2::ZERO,
+            timeline: vec![],
+            timeline_time: f64::NEG_INFINITY,
+            comparison: None,
+            gravity_drag_start: None,
+            temperature: ROOM_TEMPERATURE,
+            floor_enabled: false,
+            floor_y: screen_height() - 60.,
+            obstacles: Arc::new(vec![]),
+            group_collisions: [[true; COLLISION_GROUP_COLORS.len()]; COLLISION_GROUP_COLORS.len()],
+            substep_strain_threshold: SUBSTEP_STRAIN_THRESHOLD,
+            max_substeps: MAX_SUBSTEPS,
+            max_dt: MAX_DT,
+            last_substeps: 1,
+            sim_time: 0.,
+            sim_steps: 0,
+            average_substeps: 1.,
+            spatial_grid: SpatialGrid::new(&[], GRID_CELL_SIZE),
+            background_physics: false,
+            sleep_enabled: false,
+            sleep_speed: SLEEP_SPEED,
+            quantize_spacing: QUANTIZE_SPACING,
+            physics_worker: None,
+            pending_step: None,
+            shatter_enabled: false,
+            shatter_threshold: SHATTER_THRESHOLD,
+            ghost: None,
+            ghost_visible: true,
+            force_probe: false,
+            show_delaunay: false,
+            show_displacement: false,
+            show_rest_shape: false,
+            show_degrees: false,
+            degrees: None,
+            structure_version: 0,
+            tether_crossings: None,
+            show_body_info: false,
+            delaunay: None,
+            selected_tether: None,
+            inspected_particle: None,
+            force_history: VecDeque::with_capacity(FORCE_HISTORY_LEN),
+            show_energy_graph: false,
+            energy_history: VecDeque::with_capacity(ENERGY_HISTORY_LEN),
+            tool_sizing_factor: TOOL_SIZING_FACTOR,
+            notice: None,
+            recording: None,
+            strain_log: None,
+            step_script: StepScript::Off,
+            sim_halted: false,
+            show_ui: true,
+            background_color: BACKGROUND_COLORS[0],
+            transparent_recording: false,
+            anti_aliasing: false,
+            particle_render_radius: PARTICLE_RENDER_RADIUS,
+            circle_segments: CIRCLE_SEGMENTS,
+            tether_width: TETHER_WIDTH,
+            adaptive_quality: false,
+            target_fps: TARGET_FPS_CHOICES[2],
+            render_quality: RenderQuality::Full,
+            average_render_time: 0.,
+            quality_changed_at: 0.,
+        }
+    }
+
+    // How much the temperature scales tether stiffness (hotter is softer).
+    fn stiffness_scale(&self) -> f32 {
+        (1. - THERMAL_SOFTENING * (self.temperature - ROOM_TEMPERATURE))
+            .clamp(MIN_THERMAL_STIFFNESS, MAX_THERMAL_STIFFNESS)
+    }
+
+    // Whether any layer, hidden or not, has a drawn pixel. Stops at the first one found.
+    fn drawing_exists(&self) -> bool {
+        self.layers
+            .iter()
+            .any(|layer| layer.canvas.pixels().any(is_drawn))
+    }
+
+    fn lattice_settings(&self) -> LatticeSettings {
+        LatticeSettings {
+            lattice_type: self.lattice_type,
+            brace_squares: self.brace_squares,
+            spacing: LATTICE_SPACING,
+            stiffness: self.tether_stiffness,
+            damping: self.tether_damping,
+            total_mass: self.normalize_mass.then_some(BODY_MASS),
+            remove_orphans: self.remove_orphans,
+            weld: self.weld_duplicates,
+        }
+    }
+
+    // Canvas size the configured resolution asks for.
+    fn configured_canvas_size(&self) -> (u32, u32) {
+        match self.canvas_resolution {
+            (0, _) | (_, 0) => (screen_width() as u32, screen_height() as u32),
+            resolution => resolution,
+        }
+    }
+
+    // Size the canvas and every layer to the configured resolution, clearing them.
+    fn resize_canvas(&mut self) {
+        let (width, height) = self.configured_canvas_size();
+        self.layers = (0..LAYER_COUNT)
+            .map(|_| Layer::new(width, height))
+            .collect();
+        self.scratch_canvas = RgbaImage::new(width, height);
+        self.floor_y = self.floor_y.min(height as f32);
+        self.canvas_dirty = true;
+    }
+
+    // Canvas size in pixels. The drawing, the physics bounds and all the UI use canvas coordinates.
+    fn canvas_size(&self) -> Vec2 {
+        let canvas = &self.layers[0].canvas;
+        vec2(canvas.width() as f32, canvas.height() as f32)
+    }
+
+    // Scale and offset fitting the canvas into the window, centered between bars on the sides that
+    // are left over.
+    fn letterbox(&self) -> (f32, Vec2) {
+        let canvas = self.canvas_size();
+        let window = vec2(screen_width(), screen_height());
+        let scale = (window.x / canvas.x).min(window.y / canvas.y);
+        (scale, (window - canvas * scale) / 2.)
+    }
+
+    // Scale and screen position the canvas is drawn at. While comparing integrators the canvas is
+    // halved to fit two side by side, the world on the left and the comparison on the right.
+    fn canvas_view(&self, comparison_side: bool) -> (f32, Vec2) {
+        let (scale, offset) = self.letterbox();
+        if self.comparison.is_none() {
+            return (scale, offset);
+        }
+        let canvas = self.canvas_size();
+        let shift = vec2(
+            if comparison_side { canvas.x / 2. } else { 0. },
+            canvas.y / 4.,
+        );
+        (scale / 2., offset + shift * scale)
+    }
+
+    fn canvas_camera(&self) -> Camera2D {
+        view_camera(self.canvas_view(false))
+    }
+
+    fn comparison_camera(&self) -> Camera2D {
+        view_camera(self.canvas_view(true))
+    }
+
+    // The mouse position in canvas coordinates.
+    fn canvas_mouse(&self) -> (f32, f32) {
+        let (scale, offset) = self.canvas_view(false);
+        let (x, y) = mouse_position();
+        ((x - offset.x) / scale, (y - offset.y) / scale)
+    }
+
+    fn render_style(&self) -> RenderStyle {
+        let style = RenderStyle {
+            smooth: self.anti_aliasing,
+            particle_radius: self.particle_render_radius,
+            circle_segments: self.circle_segments,
+            tether_width: self.tether_width,
+            show_tethers: true,
+            view: self.canvas_size(),
+        };
+        match self.render_quality {
+            RenderQuality::Full => style,
+            RenderQuality::Reduced => RenderStyle {
+                smooth: false,
+                circle_segments: style.circle_segments.min(6),
+                ..style
+            },
+            RenderQuality::Minimal => RenderStyle {
+                smooth: false,
+                circle_segments: style.circle_segments.min(4),
+                show_tethers: false,
+                ..style
+            },
+        }
+    }
+
+    // Step the render quality down while the frame's work runs over the target's budget, and back up
+    // once it fits with room to spare. Each change holds for a while so the two don't alternate every
+    // frame. Recordings are kept at full quality.
+    fn update_adaptive_quality(&mut self, render_time: f32) {
+        self.average_render_time = 0.95 * self.average_render_time + 0.05 * render_time;
+        if !self.adaptive_quality || self.recording.is_some() {
+            self.render_quality = RenderQuality::Full;
+            return;
+        }
+
+        let budget = 1. / self.target_fps;
+        let held_for = get_time() - self.quality_changed_at;
+        let next = if self.average_render_time > budget && held_for > ADAPTIVE_DROP_DELAY {
+            self.render_quality.lower()
+        } else if self.average_render_time < ADAPTIVE_RESTORE_HEADROOM * budget
+            && held_for > ADAPTIVE_RESTORE_DELAY
+        {
+            self.render_quality.higher()
+        } else {
+            None
+        };
+        if let Some(quality) = next {
+            self.render_quality = quality;
+            self.quality_changed_at = get_time();
+            self.show_notice(format!(
+                "Render quality: {} ({:.1} ms per frame)",
+                quality.name(),
+                1000. * self.average_render_time
+            ));
+        }
+    }
+
+    // Replace the timeline. Changes already due aren't made, unless the simulation hasn't started.
+    fn set_timeline(&mut self, timeline: Vec<TimelineEvent>) {
+        self.timeline = timeline;
+        self.timeline_time = if self.sim_time > 0. {
+            self.sim_time
+        } else {
+            f64::NEG_INFINITY
+        };
+    }
+
+    // Make the timeline's changes that have come due since it was last run. Returns whether any were.
+    fn run_timeline(&mut self) -> bool {
+        // The sim time going backwards means the world was rebuilt, so play the timeline from the top
+        if self.sim_time < self.timeline_time {
+            self.timeline_time = f64::NEG_INFINITY;
+        }
+        let due: Vec<TimelineChange> = self
+            .timeline
+            .iter()
+            .filter(|event| event.time > self.timeline_time && event.time <= self.sim_time)
+            .map(|event| event.change)
+            .collect();
+        self.timeline_time = self.sim_time;
+
+        for &change in &due {
+            match change {
+                TimelineChange::Gravity(gravity) => {
+                    self.gravity = gravity;
+                    self.gravity_enabled = true;
+                }
+                TimelineChange::GravityEnabled(enabled) => self.gravity_enabled = enabled,
+                TimelineChange::Wind(wind) => self.wind = wind,
+                TimelineChange::Damping(damping) => {
+                    self.global_damping = damping.clamp(0., MAX_GLOBAL_DAMPING)
+                }
+            }
+        }
+        if let Some(change) = due.last() {
+            self.show_notice(format!("Timeline: {}", change.describe()));
+        }
+        !due.is_empty()
+    }
+
+    fn physics_settings(&self) -> PhysicsSettings {
+        PhysicsSettings {
+            integrator: self.integrator,
+            physics_mode: self.physics_mode,
+            pbd_iterations: self.pbd_iterations,
+            collision_response: self.collision_response,
+            collision_iterations: self.collision_iterations,
+            gravity: if self.gravity_enabled {
+                Some(self.gravity)
+            } else {
+                None
+            },
+            floor_y: if self.floor_enabled {
+                Some(self.floor_y)
+            } else {
+                None
+            },
+            obstacles: Arc::clone(&self.obstacles),
+            group_collisions: self.group_collisions,
+            stiffness_scale: self.stiffness_scale(),
+            shatter_threshold: if self.shatter_enabled {
+                Some(self.shatter_threshold)
+            } else {
+                None
+            },
+            global_damping: self.global_damping,
+            wind: self.wind,
+            sleep_speed: if self.sleep_enabled {
+                Some(self.sleep_speed)
+            } else {
+                None
+            },
+        }
+    }
+
+    // Stop stepping the simulation until the step script is changed.
+    fn halt(&mut self, reason: String) {
+        self.sim_halted = true;
+        self.show_notice(format!("Stopped at {:.2} s: {}", self.sim_time, reason));
+    }
+
+    // Forget every press and drag in progress, once their release can no longer arrive.
+    fn reset_pointer_state(&mut self) {
+        self.was_drawing = false;
+        self.grabbed_particle = None;
+        self.anchor_stroke_end = None;
+        self.launch_start = None;
+        self.gravity_drag_start = None;
+    }
+
+    // Tether indices shift when tethers break during a step.
+    fn tethers_broken(&mut self, count: usize) {
+        if count > 0 {
+            self.structure_version += 1;
+            self.selected_tether = None;
+            self.show_notice(format!("Impact shattered {} tethers", count));
+        }
+    }
+
+    // The strain log's tethers are gone once the world is replaced, so it's saved where it ends.
+    fn flush_strain_log(&mut self) {
+        if let Some(strain_log) = self.strain_log.take() {
+            match strain_log.save() {
+                Ok(path) => self.show_notice(format!(
+                    "World replaced, saved strain log to {}",
+                    path.display()
+                )),
+                Err(error) => self.show_notice(format!("Can't save strain log: {}", error)),
+            }
+        }
+    }
+
+    // Radius of the tool that's active in the mode.
+    fn active_radius_mut(&mut self, mode: Mode) -> &mut f32 {
+        match (mode, &self.draw_mode) {
+            (Mode::Create, DrawMode::Add) => &mut self.add_radius,
+            (Mode::Create, DrawMode::Remove) => &mut self.remove_radius,
+            (Mode::Sim, _) => &mut self.force_radius,
+        }
+    }
+
+    // The setting a tool's strength is kept in, for the tools that have one.
+    fn tool_strength_mut(&mut self, tool: PresetTool) -> Option<&mut f32> {
+        match tool {
+            PresetTool::Sim(SimTool::Spring) => Some(&mut self.mouse_spring_stiffness),
+            PresetTool::Sim(SimTool::Muscle) => Some(&mut self.muscle.amplitude),
+            _ => None,
+        }
+    }
+
+    // The active tool of the mode and its current settings, as a preset.
+    fn current_tool_preset(&mut self, mode: Mode) -> ToolPreset {
+        let tool = match mode {
+            Mode::Create => PresetTool::Draw(self.draw_mode),
+            Mode::Sim => PresetTool::Sim(self.sim_tool),
+        };
+        ToolPreset {
+            name: tool.name().to_owned(),
+            tool,
+            radius: *self.active_radius_mut(mode),
+            strength: self.tool_strength_mut(tool).map(|strength| *strength),
+        }
+    }
+
+    // The preset whose settings the mode's tool still has, if any.
+    fn active_tool_preset(&mut self, mode: Mode) -> Option<usize> {
+        let current = self.current_tool_preset(mode);
+        self.tool_presets.iter().position(|preset| {
+            preset.as_ref().is_some_and(|preset| {
+                preset.tool == current.tool
+                    && preset.radius == current.radius
+                    && preset.strength == current.strength
+            })
+        })
+    }
+
+    fn apply_tool_preset(&mut self, preset: &ToolPreset) {
+        match preset.tool {
+            PresetTool::Draw(draw_mode) => self.draw_mode = draw_mode,
+            PresetTool::Sim(tool) => self.sim_tool = tool,
+        }
+        *self.active_radius_mut(preset.tool.mode()) = preset.radius;
+        if let (Some(strength), Some(setting)) =
+            (preset.strength, self.tool_strength_mut(preset.tool))
+        {
+            *setting = strength;
+        }
+    }
+
+    // Read one `preset_<number>_<field>` setting. The tool comes first in the file and starts the preset.
+    fn load_preset_setting(&mut self, key: &str, value: &str) {
+        let Some((number, field)) = key
+            .strip_prefix("preset_")
+            .and_then(|rest| rest.split_once('_'))
+        else {
+            return;
+        };
+        let Some(slot) = number
+            .parse::<usize>()
+            .ok()
+            .filter(|number| (1..=TOOL_PRESET_COUNT).contains(number))
+            .map(|number| &mut self.tool_presets[number - 1])
+        else {
+            return;
+        };
+        match (field, slot.as_mut()) {
+            ("tool", _) => {
+                *slot = PresetTool::ALL
+                    .into_iter()
+                    .find(|tool| tool.name() == value)
+                    .map(|tool| ToolPreset {
+                        name: tool.name().to_owned(),
+                        tool,
+                        radius: MIN_TOOL_RADIUS,
+                        strength: None,
+                    });
+            }
+            ("name", Some(preset)) => preset.name = value.to_owned(),
+            ("radius", Some(preset)) => parse_setting(&mut preset.radius, value),
+            ("strength", Some(preset)) => preset.strength = value.parse().ok(),
+            _ => (),
+        }
+    }
+
+    fn show_notice(&mut self, text: String) {
+        self.notice = Some((text, get_time()));
+    }
+
+    // Composite the visible layers bottom to top into a single image.
+    fn flatten_layers(&self) -> RgbaImage {
+        let mut flattened = RgbaImage::new(
+            self.layers[0].canvas.width(),
+            self.layers[0].canvas.height(),
+        );
+        self.layers
+            .iter()
+            .filter(|layer| layer.visible)
+            .for_each(|layer| image::imageops::overlay(&mut flattened, &layer.canvas, 0, 0));
+        flattened
+    }
+
+    // Write the user's preferred settings as simple `key = value` TOML.
+    fn save_config(&self) -> std::io::Result<()> {
+        let settings = [
+            ("add_radius", self.add_radius.to_string()),
+            ("remove_radius", self.remove_radius.to_string()),
+            ("force_radius", self.force_radius.to_string()),
+            ("tool_sizing_factor", self.tool_sizing_factor.to_string()),
+            ("normalize_mass", self.normalize_mass.to_string()),
+            ("remove_orphans", self.remove_orphans.to_string()),
+            ("weld_duplicates", self.weld_duplicates.to_string()),
+            ("build_from_empty", self.build_from_empty.to_string()),
+            ("lattice_type", format!("\"{}\"", self.lattice_type.name())),
+            ("brace_squares", self.brace_squares.to_string()),
+            ("show_grid", self.show_grid.to_string()),
+            ("snap_to_grid", self.snap_to_grid.to_string()),
+            ("grid_spacing", self.grid_spacing.to_string()),
+            ("tether_stiffness", self.tether_stiffness.to_string()),
+            ("tether_damping", self.tether_damping.to_string()),
+            ("global_damping", self.global_damping.to_string()),
+            (
+                "mouse_spring_stiffness",
+                self.mouse_spring_stiffness.to_string(),
+            ),
+            ("muscle_amplitude", self.muscle.amplitude.to_string()),
+            ("muscle_frequency", self.muscle.frequency.to_string()),
+            ("integrator", format!("\"{}\"", self.integrator.name())),
+            (
+                "collision_response",
+                format!("\"{}\"", self.collision_response.name()),
+            ),
+            ("physics_mode", format!("\"{}\"", self.physics_mode.name())),
+            ("pbd_iterations", self.pbd_iterations.to_string()),
+            (
+                "collision_iterations",
+                self.collision_iterations.to_string(),
+            ),
+            ("background_physics", self.background_physics.to_string()),
+            ("sleep_enabled", self.sleep_enabled.to_string()),
+            ("sleep_speed", self.sleep_speed.to_string()),
+            ("quantize_spacing", self.quantize_spacing.to_string()),
+            ("shatter_enabled", self.shatter_enabled.to_string()),
+            ("shatter_threshold", self.shatter_threshold.to_string()),
+            ("gravity_enabled", self.gravity_enabled.to_string()),
+            ("gravity_x", self.gravity.x.to_string()),
+            ("gravity_y", self.gravity.y.to_string()),
+            ("floor_enabled", self.floor_enabled.to_string()),
+            (
+                "substep_strain_threshold",
+                self.substep_strain_threshold.to_string(),
+            ),
+            ("max_substeps", self.max_substeps.to_string()),
+            ("max_dt", self.max_dt.to_string()),
+            ("anti_aliasing", self.anti_aliasing.to_string()),
+            (
+                "background_color",
+                format!("\"{:06x}\"", self.background_color),
+            ),
+            (
+                "transparent_recording",
+                self.transparent_recording.to_string(),
+            ),
+            (
+                "particle_render_radius",
+                self.particle_render_radius.to_string(),
+            ),
+            ("canvas_width", self.canvas_resolution.0.to_string()),
+            ("canvas_height", self.canvas_resolution.1.to_string()),
+            ("circle_segments", self.circle_segments.to_string()),
+            ("tether_width", self.tether_width.to_string()),
+            ("adaptive_quality", self.adaptive_quality.to_string()),
+            ("target_fps", self.target_fps.to_string()),
+        ];
+        let mut lines: Vec<String> = settings
+            .iter()
+            .map(|(key, value)| format!("{} = {}", key, value))
+            .collect();
+        for (i, preset) in self.tool_presets.iter().enumerate() {
+            let Some(preset) = preset else {
+                continue;
+            };
+            lines.push(format!(
+                "preset_{}_tool = \"{}\"",
+                i + 1,
+                preset.tool.name()
+            ));
+            lines.push(format!("preset_{}_name = \"{}\"", i + 1, preset.name));
+            lines.push(format!("preset_{}_radius = {}", i + 1, preset.radius));
+            if let Some(strength) = preset.strength {
+                lines.push(format!("preset_{}_strength = {}", i + 1, strength));
+            }
+        }
+        fs::write(CONFIG_PATH, lines.join("\n") + "\n")
+    }
+
+    // Apply whatever settings can be read from the config file, keeping the defaults for anything
+    // missing, unknown, or malformed.
+    fn load_config(&mut self) {
+        let Ok(text) = fs::read_to_string(CONFIG_PATH) else {
+            return;
+        };
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().trim_matches('"');
+            match key.trim() {
+                "add_radius" => parse_setting(&mut self.add_radius, value),
+                "remove_radius" => parse_setting(&mut self.remove_radius, value),
+                "force_radius" => parse_setting(&mut self.force_radius, value),
+                "tool_sizing_factor" => parse_setting(&mut self.tool_sizing_factor, value),
+                "normalize_mass" => parse_setting(&mut self.normalize_mass, value),
+                "remove_orphans" => parse_setting(&mut self.remove_orphans, value),
+                "weld_duplicates" => parse_setting(&mut self.weld_duplicates, value),
+                "build_from_empty" => parse_setting(&mut self.build_from_empty, value),
+                "lattice_type" => {
+                    if let Some(lattice_type) = [LatticeType::Hexagonal, LatticeType::Square]
+                        .into_iter()
+                        .find(|lattice_type| lattice_type.name() == value)
+                    {
+                        self.lattice_type = lattice_type;
+                    }
+                }
+                "brace_squares" => parse_setting(&mut self.brace_squares, value),
+                "show_grid" => parse_setting(&mut self.show_grid, value),
+                "snap_to_grid" => parse_setting(&mut self.snap_to_grid, value),
+                "grid_spacing" => parse_setting(&mut self.grid_spacing, value),
+                "tether_stiffness" => parse_setting(&mut self.tether_stiffness, value),
+                "tether_damping" => parse_setting(&mut self.tether_damping, value),
+                "global_damping" => parse_setting(&mut self.global_damping, value),
+                "mouse_spring_stiffness" => parse_setting(&mut self.mouse_spring_stiffness, value),
+                "muscle_amplitude" => parse_setting(&mut self.muscle.amplitude, value),
+                "muscle_frequency" => parse_setting(&mut self.muscle.frequency, value),
+                "integrator" => {
+                    if let Some(integrator) = [Integrator::SemiImplicitEuler, Integrator::Leapfrog]
+                        .into_iter()
+                        .find(|integrator| integrator.name() == value)
+                    {
+                        self.integrator = integrator;
+                    }
+                }
+                "physics_mode" => {
+                    if let Some(physics_mode) = [PhysicsMode::ForceBased, PhysicsMode::Pbd]
+                        .into_iter()
+                        .find(|physics_mode| physics_mode.name() == value)
+                    {
+                        self.physics_mode = physics_mode;
+                    }
+                }
+                "pbd_iterations" => parse_setting(&mut self.pbd_iterations, value),
+                "collision_iterations" => parse_setting(&mut self.collision_iterations, value),
+                "background_physics" => parse_setting(&mut self.background_physics, value),
+                "sleep_enabled" => parse_setting(&mut self.sleep_enabled, value),
+                "sleep_speed" => parse_setting(&mut self.sleep_speed, value),
+                "quantize_spacing" => parse_setting(&mut self.quantize_spacing, value),
+                "shatter_enabled" => parse_setting(&mut self.shatter_enabled, value),
+                "shatter_threshold" => parse_setting(&mut self.shatter_threshold, value),
+                "collision_response" => {
+                    if let Some(response) = [CollisionResponse::Penalty, CollisionResponse::Impulse]
+                        .into_iter()
+                        .find(|response| response.name() == value)
+                    {
+                        self.collision_response = response;
+                    }
+                }
+                "gravity_enabled" => parse_setting(&mut self.gravity_enabled, value),
+                "gravity_x" => parse_setting(&mut self.gravity.x, value),
+                "gravity_y" => parse_setting(&mut self.gravity.y, value),
+                "floor_enabled" => parse_setting(&mut self.floor_enabled, value),
+                "substep_strain_threshold" => {
+                    parse_setting(&mut self.substep_strain_threshold, value)
+                }
+                "max_substeps" => parse_setting(&mut self.max_substeps, value),
+                "max_dt" => parse_setting(&mut self.max_dt, value),
+                "anti_aliasing" => parse_setting(&mut self.anti_aliasing, value),
+                "background_color" => {
+                    if let Ok(color) = u32::from_str_radix(value.trim_start_matches('#'), 16) {
+                        self.background_color = color & 0xFFFFFF;
+                    }
+                }
+                "transparent_recording" => parse_setting(&mut self.transparent_recording, value),
+                "particle_render_radius" => parse_setting(&mut self.particle_render_radius, value),
+                "canvas_width" => parse_setting(&mut self.canvas_resolution.0, value),
+                "canvas_height" => parse_setting(&mut self.canvas_resolution.1, value),
+                "circle_segments" => parse_setting(&mut self.circle_segments, value),
+                "tether_width" => parse_setting(&mut self.tether_width, value),
+                "adaptive_quality" => parse_setting(&mut self.adaptive_quality, value),
+                "target_fps" => parse_setting(&mut self.target_fps, value),
+                key if key.starts_with("preset_") => self.load_preset_setting(key, value),
+                _ => (),
+            }
+        }
+
+        // Hand-edited values could be outside of what the controls allow
+        self.add_radius = self.add_radius.clamp(MIN_TOOL_RADIUS, MAX_TOOL_RADIUS);
+        self.remove_radius = self.remove_radius.clamp(MIN_TOOL_RADIUS, MAX_TOOL_RADIUS);
+        self.force_radius = self.force_radius.clamp(MIN_TOOL_RADIUS, MAX_TOOL_RADIUS);
+        for preset in self.tool_presets.iter_mut().flatten() {
+            preset.radius = preset.radius.clamp(MIN_TOOL_RADIUS, MAX_TOOL_RADIUS);
+        }
+        self.tool_sizing_factor = self
+            .tool_sizing_factor
+            .clamp(MIN_TOOL_SIZING_FACTOR, MAX_TOOL_SIZING_FACTOR);
+        // Out of range tether values are fixed loudly, they would otherwise only show as an exploding body
+        let mut warnings = vec![];
+        if !self.tether_stiffness.is_finite() || self.tether_stiffness < 0. {
+            warnings.push(format!(
+                "tether_stiffness {} isn't a stiffness, using {}",
+                self.tether_stiffness, TETHER_STIFFNESS
+            ));
+            self.tether_stiffness = TETHER_STIFFNESS;
+        } else if !(MIN_TETHER_STIFFNESS..=MAX_TETHER_STIFFNESS).contains(&self.tether_stiffness) {
+            let clamped = self
+                .tether_stiffness
+                .clamp(MIN_TETHER_STIFFNESS, MAX_TETHER_STIFFNESS);
+            warnings.push(format!(
+                "tether_stiffness {} is out of range, using {}",
+                self.tether_stiffness, clamped
+            ));
+            self.tether_stiffness = clamped;
+        }
+        if !self.tether_damping.is_finite() || self.tether_damping < 0. {
+            warnings.push(format!(
+                "tether_damping {} isn't a damping, using 0",
+                self.tether_damping
+            ));
+            self.tether_damping = 0.;
+        }
+        self.max_substeps = self.max_substeps.max(1);
+        if !(self.max_dt > 0. && self.max_dt <= MAX_DT_LIMIT) {
+            self.max_dt = MAX_DT;
+        }
+        self.global_damping = self.global_damping.clamp(0., MAX_GLOBAL_DAMPING);
+        self.sleep_speed = self.sleep_speed.max(0.);
+        self.quantize_spacing = self
+            .quantize_spacing
+            .clamp(MIN_QUANTIZE_SPACING, MAX_QUANTIZE_SPACING);
+        self.pbd_iterations = self.pbd_iterations.clamp(1, MAX_PBD_ITERATIONS);
+        self.mouse_spring_stiffness = self
+            .mouse_spring_stiffness
+            .clamp(MIN_MOUSE_SPRING_STIFFNESS, MAX_MOUSE_SPRING_STIFFNESS);
+        self.collision_iterations = self.collision_iterations.clamp(1, MAX_COLLISION_ITERATIONS);
+        self.grid_spacing = self.grid_spacing.clamp(MIN_GRID_SPACING, MAX_GRID_SPACING);
+        self.shatter_threshold = self
+            .shatter_threshold
+            .clamp(MIN_SHATTER_THRESHOLD, MAX_SHATTER_THRESHOLD);
+        self.particle_render_radius = self
+            .particle_render_radius
+            .clamp(MIN_PARTICLE_RENDER_RADIUS, MAX_PARTICLE_RENDER_RADIUS);
+        self.canvas_resolution = (
+            self.canvas_resolution.0.min(MAX_CANVAS_SIZE),
+            self.canvas_resolution.1.min(MAX_CANVAS_SIZE),
+        );
+        self.circle_segments = self
+            .circle_segments
+            .clamp(MIN_CIRCLE_SEGMENTS, MAX_CIRCLE_SEGMENTS);
+        self.tether_width = self.tether_width.clamp(MIN_TETHER_WIDTH, MAX_TETHER_WIDTH);
+        self.target_fps = self.target_fps.clamp(
+            TARGET_FPS_CHOICES[0],
+            TARGET_FPS_CHOICES[TARGET_FPS_CHOICES.len() - 1],
+        );
+
+        if let Some(warning) = self.lattice_timestep_warning() {
+            warnings.push(warning);
+        }
+        if !warnings.is_empty() {
+            self.show_notice(format!("{}: {}", CONFIG_PATH, warnings.join("; ")));
+        }
+    }
+
+    // Warn if the lattices the settings build would be too stiff for the longest step the settings allow,
+    // judging a particle inside the lattice as critical_timestep will once it's built.
+    fn lattice_timestep_warning(&self) -> Option<String> {
+        if let PhysicsMode::Pbd = self.physics_mode {
+            return None;
+        }
+        let lattice = self.lattice_settings();
+        let stiffness_sum = lattice.lattice_type.interior_degree(lattice.brace_squares) as f32
+            * lattice.stiffness
+            * self.stiffness_scale();
+        let longest = self.max_dt / self.max_substeps as f32;
+        match lattice.total_mass {
+            // build_lattice gives every particle a mass of 1
+            None => {
+                let critical = critical_step_for(stiffness_sum, 1.);
+                (critical < longest).then(|| {
+                    format!(
+                        "tether_stiffness {} is only stable below {:.2} ms steps (up to {:.2} ms \
+                         now), raise max_substeps to {}",
+                        lattice.stiffness,
+                        critical * 1000.,
+                        longest * 1000.,
+                        (self.max_dt / critical).ceil()
+                    )
+                })
+            }
+            // The mass is spread over each body, so the particles of bigger bodies are lighter. Only
+            // warn if a body filling the canvas would be too big to stay stable.
+            Some(total_mass) => {
+                let (width, height) = self.configured_canvas_size();
+                let canvas_body = lattice.lattice_type.particle_density(lattice.spacing)
+                    * (width * height) as f32;
+                let critical = critical_step_for(stiffness_sum, total_mass / canvas_body.max(1.));
+                (critical < longest).then(|| {
+                    // The lightest particle still stable at the longest step, solving
+                    // critical_step_for for the mass
+                    let largest_body = total_mass / (stiffness_sum * (longest / 2.).powi(2));
+                    format!(
+                        "tether_stiffness {} with normalize_mass makes bodies of over {:.0} \
+                         particles unstable at {:.2} ms steps, raise max_substeps to {} for one \
+                         filling the canvas",
+                        lattice.stiffness,
+                        largest_body,
+                        longest * 1000.,
+                        (self.max_dt / critical).ceil()
+                    )
+                })
+            }
+        }
+    }
+}
+
+// Overwrite the setting only if the value parses.
+fn parse_setting<T: FromStr>(setting: &mut T, value: &str) {
+    if let Ok(parsed) = value.parse() {
+        *setting = parsed;
+    }
+}
+
+// I hate lines.
+fn draw_rounded_line(
+    image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    pos1: (f32, f32),
+    pos2: (f32, f32),
+    width: f32,
+    color: Rgba<u8>,
+) {
+    // Keep the stroke on the canvas, the mouse can be right at (or past) its edge
+    let pos1 = clamp_to_canvas(image, pos1);
+    let pos2 = clamp_to_canvas(image, pos2);
+
+    // Draw the end caps
+    imageproc::drawing::draw_filled_circle_mut(
+        image,
+        (pos1.0 as i32, pos1.1 as i32),
+        (width / 2.) as i32,
+        color,
+    );
+    imageproc::drawing::draw_filled_circle_mut(
+        image,
+        (pos2.0 as i32, pos2.1 as i32),
+        (width / 2.) as i32,
+        color,
+    );
+
+    let pos1_vec = vec2(pos1.0, pos1.1);
+    let pos2_vec = vec2(pos2.0, pos2.1);
+    let distance = pos2_vec.distance(pos1_vec);
+
+    // Avoid trying to draw an empty polygon
+    if (distance as i32) < 1 {
+        return;
+    }
+
+    let line_direction = (pos2_vec - pos1_vec).normalize();
+    let line_perp = line_direction.perp();
+
+    let corner1 = pos1_vec + line_perp * width / 2.;
+    let corner2 = pos1_vec - line_perp * width / 2.;
+    let corner3 = pos2_vec - line_perp * width / 2.;
+    let corner4 = pos2_vec + line_perp * width / 2.;
+
+    imageproc::drawing::draw_polygon_mut(
+        image,
+        &[
+            Point::new(corner1.x as i32, corner1.y as i32),
+            Point::new(corner2.x as i32, corner2.y as i32),
+            Point::new(corner3.x as i32, corner3.y as i32),
+            Point::new(corner4.x as i32, corner4.y as i32),
+        ],
+        color,
+    );
+}
+
+// Clamp a position to the nearest valid pixel of the canvas.
+fn clamp_to_canvas(canvas: &ImageBuffer<Rgba<u8>, Vec<u8>>, pos: (f32, f32)) -> (f32, f32) {
+    (
+        pos.0.clamp(0., canvas.width().saturating_sub(1) as f32),
+        pos.1.clamp(0., canvas.height().saturating_sub(1) as f32),
+    )
+}
+
+// Nearest grid point to the position.
+fn snap_to_grid(pos: (f32, f32), spacing: f32) -> (f32, f32) {
+    (
+        (pos.0 / spacing).round() * spacing,
+        (pos.1 / spacing).round() * spacing,
+    )
+}
+
+// Faint lines every spacing pixels across the canvas.
+fn render_grid(spacing: f32, size: Vec2) {
+    let color = Color::from_rgba(203, 206, 209, 25);
+    let (w, h) = (size.x, size.y);
+    for i in 0..=(w / spacing) as u32 {
+        let x = i as f32 * spacing;
+        draw_line(x, 0., x, h, 1., color);
+    }
+    for i in 0..=(h / spacing) as u32 {
+        let y = i as f32 * spacing;
+        draw_line(0., y, w, y, 1., color);
+    }
+}
+
+// Implementation of the s2sphere flood fill algorithm. https://github.com/qedus/sphere
+// Pixels the pattern leaves as holes are cleared instead of filled.
+fn flood_fill(
+    create_canvas: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    start_pos: (u32, u32),
+    fill_color: Rgba<u8>,
+    pattern: FillPattern,
+) {
+    let w = create_canvas.width();
+    let h = create_canvas.height();
+    if w == 0 || h == 0 {
+        return;
+    }
+    let start_pos = (start_pos.0.min(w - 1), start_pos.1.min(h - 1));
+    let start_color = *create_canvas.get_pixel(start_pos.0, start_pos.1);
+
+    // Nothing would change
+    if start_color == fill_color && matches!(pattern, FillPattern::Solid) {
+        return;
+    }
+
+    // Holes can keep the start color, so filled pixels are tracked separately to prevent infinite loops
+    let mut visited = vec![false; (w * h) as usize];
+
+    let mut frontier = vec![start_pos];
+
+    // Keep going until algo can't find more unfilled pixels
+    while !frontier.is_empty() {
+        let (x, y) = frontier.pop().unwrap();
+        let this_color = *create_canvas.get_pixel(x, y);
+
+        // Skip branching out from this pixel if it is a "border"
+        if this_color != start_color || visited[(y * w + x) as usize] {
+            continue;
+        }
+        visited[(y * w + x) as usize] = true;
+
+        // Color the pixel (like infection)
+        let color = if pattern.covers(x, y) {
+            fill_color
+        } else {
+            Rgba([0, 0, 0, 0])
+        };
+        create_canvas.draw_pixel(x, y, color);
+
+        // Branch out to explore other pixels.
+        // Ensures that boundaries are not exceeded.
+        if x + 1 < w {
+            frontier.push((x + 1, y))
+        }
+        if x > 0 {
+            frontier.push((x - 1, y))
+        }
+        if y + 1 < h {
+            frontier.push((x, y + 1))
+        }
+        if y > 0 {
+            frontier.push((x, y - 1))
+        }
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+// The bodies in the versioned interchange format:
+//
+// {
+//   "format": "hexagonal_softbodies.mesh",
+//   "version": 1,
+//   "particles": [{ "id": 0, "x": 0, "y": 0, "vx": 0, "vy": 0, "mass": 1, "anchored": false }, ...],
+//   "tethers": [{ "p1": 0, "p2": 1, "k": 10000, "damping": 0, "rest_length": 10 }, ...]
+// }
+//
+// Tethers refer to particles by id. Positions are in pixels with y down, velocities in pixels per second.
+// Field names and meanings stay fixed within a version; changes bump the version and get a migration.
+fn export_mesh_json(physics_objects: &(Vec<Particle>, Vec<Tether>)) -> String {
+    // JSON has no NaN or infinity, so those are written as null (and refused on import)
+    let number = |x: f32| {
+        if x.is_finite() {
+            x.to_string()
+        } else {
+            "null".to_owned()
+        }
+    };
+    let particles: Vec<String> = physics_objects
+        .0
+        .iter()
+        .enumerate()
+        .map(|(id, particle)| {
+            format!(
+                "    {{\"id\": {}, \"x\": {}, \"y\": {}, \"vx\": {}, \"vy\": {}, \"mass\": {}, \"anchored\": {}}}",
+                id,
+                number(particle.position.x),
+                number(particle.position.y),
+                number(particle.velocity.x),
+                number(particle.velocity.y),
+                number(particle.mass),
+                particle.anchored
+            )
+        })
+        .collect();
+    let tethers: Vec<String> = physics_objects
+        .1
+        .iter()
+        .map(|tether| {
+            // A muscle's rest length is the middle of its cycle
+            let rest_length = tether
+                .actuator
+                .map_or(tether.initial_dist, |actuator| actuator.rest_length);
+            format!(
+                "    {{\"p1\": {}, \"p2\": {}, \"k\": {}, \"damping\": {}, \"rest_length\": {}}}",
+                tether.p1_index,
+                tether.p2_index,
+                number(tether.k),
+                number(tether.damping_constant),
+                number(rest_length)
+            )
+        })
+        .collect();
+    format!(
+        "{{\n  \"format\": \"{}\",\n  \"version\": {},\n  \"particles\": [\n{}\n  ],\n  \"tethers\": [\n{}\n  ]\n}}\n",
+        MESH_FORMAT,
+        MESH_SCHEMA_VERSION,
+        particles.join(",\n"),
+        tethers.join(",\n")
+    )
+}
+
+// Read bodies written by export_mesh_json (or another tool following its schema), upgrading older
+// versions first. Every field is checked, and tethers must join two different, existing particles.
+fn import_mesh_json(text: &str) -> Result<(Vec<Particle>, Vec<Tether>), String> {
+    let mesh = Json::parse(text)?;
+    if mesh.get("format").and_then(Json::as_str) != Some(MESH_FORMAT) {
+        return Err(format!("not a {} file", MESH_FORMAT));
+    }
+    let version = mesh
+        .get("version")
+        .and_then(Json::as_f64)
+        .ok_or("missing version")?;
+    let mesh = migrate_mesh(mesh, version as u32)?;
+
+    let number = |object: &Json, key: &str, what: &str| {
+        object
+            .get(key)
+            .and_then(Json::as_f64)
+            .map(|x| x as f32)
+            .filter(|x| x.is_finite())
+            .ok_or_else(|| format!("{} needs a number for \"{}\"", what, key))
+    };
+    // Ids are whole numbers, though any unique ones will do
+    let id = |object: &Json, key: &str, what: &str| {
+        object
+            .get(key)
+            .and_then(Json::as_f64)
+            .filter(|id| id.fract() == 0.)
+            .map(|id| id as i64)
+            .ok_or_else(|| format!("{} needs a whole number for \"{}\"", what, key))
+    };
+    let list = |key: &str| {
+        mesh.get(key)
+            .and_then(Json::as_array)
+            .ok_or_else(|| format!("missing \"{}\" list", key))
+    };
+
+    let mut particles = vec![];
+    let mut indices = HashMap::new(); // Particle id to index
+    for (i, object) in list("particles")?.iter().enumerate() {
+        let what = format!("particle {}", i);
+        let id = id(object, "id", &what)?;
+        if indices.insert(id, particles.len()).is_some() {
+            return Err(format!("{} repeats id {}", what, id));
+        }
+        let mut particle = Particle::new(
+            vec2(number(object, "x", &what)?, number(object, "y", &what)?),
+            vec2(number(object, "vx", &what)?, number(object, "vy", &what)?),
+            number(object, "mass", &what)?,
+        );
+        if particle.mass <= 0. {
+            return Err(format!("{} needs a positive mass", what));
+        }
+        particle.anchored = object
+            .get("anchored")
+            .and_then(Json::as_bool)
+            .ok_or_else(|| format!("{} needs true or false for \"anchored\"", what))?;
+        particles.push(particle);
+    }
+
+    let mut tethers = vec![];
+    let mut joined = HashSet::new();
+    for (i, object) in list("tethers")?.iter().enumerate() {
+        let what = format!("tether {}", i);
+        let index = |key: &str| {
+            let id = id(object, key, &what)?;
+            indices
+                .get(&id)
+                .copied()
+                .ok_or_else(|| format!("{} refers to missing particle {}", what, id))
+        };
+        let (p1, p2) = (index("p1")?, index("p2")?);
+        if p1 == p2 {
+            return Err(format!("{} joins a particle to itself", what));
+        }
+        if !joined.insert((p1.min(p2), p1.max(p2))) {
+            return Err(format!("{} joins particles that are already joined", what));
+        }
+        let rest_length = number(object, "rest_length", &what)?;
+        if rest_length <= 0. {
+            return Err(format!("{} needs a positive rest length", what));
+        }
+        let (k, damping) = (
+            number(object, "k", &what)?,
+            number(object, "damping", &what)?,
+        );
+        if k < 0. || damping < 0. {
+            return Err(format!(
+                "{} can't have a negative stiffness or damping",
+                what
+            ));
+        }
+        let mut tether = Tether::new(p1, p2, k, damping, &particles);
+        tether.initial_dist = rest_length;
+        tethers.push(tether);
+    }
+    Ok((particles, tethers))
+}
+
+// Bring a mesh written with an older schema version up to MESH_SCHEMA_VERSION.
+fn migrate_mesh(mesh: Json, version: u32) -> Result<Json, String> {
+    match version {
+        MESH_SCHEMA_VERSION => Ok(mesh),
+        // When the schema changes, each older version gets an arm here upgrading it by one version
+        version if version > MESH_SCHEMA_VERSION => Err(format!(
+            "version {} is newer than this build reads ({})",
+            version, MESH_SCHEMA_VERSION
+        )),
+        version => Err(format!("unknown version {}", version)),
+    }
+}
+
+// Just enough JSON to read interchange files back, since the crate doesn't depend on a JSON library.
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = JsonParser { text, position: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position < text.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    text: &'a str,
+    position: usize, // Byte offset of the next character
+}
+
+impl JsonParser<'_> {
+    fn error(&self, reason: &str) -> String {
+        format!("{} at byte {}", reason, self.position)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.next() == Some(expected) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", expected)))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Json::String),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('n') => self.literal("null", Json::Null),
+            Some(_) => self.number(),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if self.text[self.position..].starts_with(word) {
+            self.position += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("unknown value"))
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(c))
+        {
+            self.position += 1;
+        }
+        self.text[start..self.position]
+            .parse()
+            .map(Json::Number)
+            .map_err(|_| self.error("invalid number"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self
+                .next()
+                .ok_or_else(|| self.error("unterminated string"))?
+            {
+                '"' => return Ok(string),
+                '\\' => {
+                    let escaped = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let hex = self
+                                .text
+                                .get(self.position..self.position + 4)
+                                .ok_or_else(|| self.error("short unicode escape"))?;
+                            self.position += 4;
+                            u32::from_str_radix(hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        Some(c @ ('"' | '\\' | '/')) => c,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    string.push(escaped);
+                }
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut items = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Json::Array(items)),
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut fields = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Json::Object(fields)),
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+// Per-step strain of a set of tethers, kept in memory and written out as JSON when stopped.
+struct StrainLog {
+    tethers: Vec<(usize, Option<usize>)>, // Index when logging started, and now (None once removed)
+    samples: Vec<Vec<f32>>, // One list of strains per step, in the same order as tethers
+}
+
+impl StrainLog {
+    fn new(tethers: Vec<usize>) -> Self {
+        Self {
+            tethers: tethers.into_iter().map(|i| (i, Some(i))).collect(),
+            samples: vec![],
+        }
+    }
+
+    // Follow the logged tethers past the removal of others, given the removed indices in ascending order.
+    fn tethers_removed(&mut self, removed: &[usize]) {
+        if removed.is_empty() {
+            return;
+        }
+        for (_, current) in self.tethers.iter_mut() {
+            *current = current.and_then(|i| removed.binary_search(&i).err().map(|shift| i - shift));
+        }
+    }
+
+    // Sample the logged tethers after a step that broke the given ones, see tethers_removed.
+    fn record_step(&mut self, physics_objects: &(Vec<Particle>, Vec<Tether>), broken: &[usize]) {
+        self.tethers_removed(broken);
+        self.samples.push(
+            self.tethers
+                .iter()
+                .map(|&(_, current)| {
+                    current.map_or(f32::NAN, |i| {
+                        physics_objects.1[i].strain(&physics_objects.0)
+                    })
+                })
+                .collect(),
+        );
+    }
+
+    // Write `{ "tether_index": [strain_per_step, ...], ... }` and return where it went.
+    fn save(&self) -> std::io::Result<PathBuf> {
+        let series: Vec<String> = self
+            .tethers
+            .iter()
+            .enumerate()
+            .map(|(column, (tether, _))| {
+                let strains: Vec<String> = self
+                    .samples
+                    .iter()
+                    // JSON has no NaN, so steps after a tether was removed are written as null
+                    .map(|step| match step[column] {
+                        strain if strain.is_finite() => strain.to_string(),
+                        _ => "null".to_owned(),
+                    })
+                    .collect();
+                format!("\"{}\":[{}]", tether, strains.join(","))
+            })
+            .collect();
+
+        fs::create_dir_all("recordings")?;
+        let path = PathBuf::from(format!("recordings/strain_{}.json", unix_timestamp()));
+        fs::write(&path, format!("{{{}}}", series.join(",")))?;
+        Ok(path)
+    }
+}
+
+// Built-in logic run on the world after every physics step, see step_with.
+enum StepScript {
+    Off,
+    StopWhenSettled,
+    LogEnergy(Vec<(f64, f32, f32)>), // Sim time, kinetic and total energy after each step
+}
+
+impl StepScript {
+    fn name(&self) -> &'static str {
+        match self {
+            StepScript::Off => "Off",
+            StepScript::StopWhenSettled => "Stop When Settled",
+            StepScript::LogEnergy(_) => "Log Energy",
+        }
+    }
+
+    fn next(&self) -> Self {
+        match self {
+            StepScript::Off => StepScript::StopWhenSettled,
+            StepScript::StopWhenSettled => StepScript::LogEnergy(vec![]),
+            StepScript::LogEnergy(_) => StepScript::Off,
+        }
+    }
+
+    // Breaks with the reason to stop the simulation.
+    fn after_step(
+        &mut self,
+        physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+        time: f64,
+        stiffness_scale: f32,
+    ) -> ControlFlow<String> {
+        match self {
+            StepScript::Off => (),
+            StepScript::StopWhenSettled => {
+                let settled = !physics_objects.0.is_empty()
+                    && physics_objects
+                        .0
+                        .iter()
+                        .all(|particle| particle.velocity.length() < SLEEP_SPEED);
+                if settled {
+                    return ControlFlow::Break("every particle has settled".to_owned());
+                }
+            }
+            StepScript::LogEnergy(samples) => samples.push((
+                time,
+                kinetic_energy(&physics_objects.0),
+                total_energy(physics_objects, stiffness_scale),
+            )),
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+// Write `{ "time": [...], "kinetic": [...], "total": [...] }` and return where it went.
+fn save_energy_log(samples: &[(f64, f32, f32)]) -> std::io::Result<PathBuf> {
+    let column = |value: &dyn Fn(&(f64, f32, f32)) -> String| {
+        samples.iter().map(value).collect::<Vec<_>>().join(",")
+    };
+    let json = format!(
+        "{{\"time\":[{}],\"kinetic\":[{}],\"total\":[{}]}}",
+        column(&|sample| sample.0.to_string()),
+        column(&|sample| sample.1.to_string()),
+        column(&|sample| sample.2.to_string())
+    );
+
+    fs::create_dir_all("recordings")?;
+    let path = PathBuf::from(format!("recordings/energy_{}.json", unix_timestamp()));
+    fs::write(&path, json)?;
+    Ok(path)
+}
+
+// Frames are written to disk as they are captured to keep long recordings out of memory.
+struct Recording {
+    directory: PathBuf,
+    frame_count: u32,
+    transparent_target: Option<RenderTarget>, // Off-screen target the softbody alone is drawn into
+}
+
+impl Recording {
+    fn start(transparent: bool, size: Vec2) -> std::io::Result<Self> {
+        let directory = PathBuf::from(format!("recordings/{}", unix_timestamp()));
+        fs::create_dir_all(&directory)?;
+        Ok(Self {
+            directory,
+            frame_count: 0,
+            transparent_target: transparent.then(|| render_target(size.x as u32, size.y as u32)),
+        })
+    }
+
+    // Save the next numbered PNG: what is currently on screen, or just the physics objects over
+    // transparency when recording transparently.
+    fn capture_frame(
+        &mut self,
+        physics_objects: &(Vec<Particle>, Vec<Tether>),
+        style: RenderStyle,
+    ) -> image::ImageResult<()> {
+        let screen = match self.transparent_target {
+            Some(target) => {
+                render_offscreen(target, physics_objects, style, Color::new(0., 0., 0., 0.))
+            }
+            None => get_screen_data(),
+        };
+        let mut frame =
+            RgbaImage::from_raw(screen.width as u32, screen.height as u32, screen.bytes)
+                .expect("Screen data doesn't match its dimensions.");
+        // The screen is read bottom-up
+        image::imageops::flip_vertical_in_place(&mut frame);
+
+        frame.save(
+            self.directory
+                .join(format!("frame_{:05}.png", self.frame_count)),
+        )?;
+        self.frame_count += 1;
+        Ok(())
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        if let Some(target) = self.transparent_target {
+            target.delete();
+        }
+    }
+}
+
+// Draw just the physics objects over the background into the target, which the canvas is stretched
+// to fill, and read the result back.
+fn render_offscreen(
+    target: RenderTarget,
+    physics_objects: &(Vec<Particle>, Vec<Tether>),
+    style: RenderStyle,
+    background: Color,
+) -> Image {
+    set_camera(&Camera2D {
+        render_target: Some(target),
+        ..Camera2D::from_display_rect(macroquad::math::Rect::new(
+            0.,
+            0.,
+            style.view.x,
+            style.view.y,
+        ))
+    });
+    clear_background(background);
+    render_physics_objects(physics_objects, style);
+    set_default_camera();
+
+    // Draw calls are batched, so make sure they reach the target before reading it back
+    unsafe {
+        get_internal_gl().flush();
+    }
+    target.texture.get_texture_data()
+}
+
+// Render the physics objects at scale times the canvas resolution and save them as a new PNG.
+fn export_still(
+    physics_objects: &(Vec<Particle>, Vec<Tether>),
+    style: RenderStyle,
+    background: Color,
+    scale: f32,
+) -> image::ImageResult<PathBuf> {
+    let scale = scale.min(MAX_EXPORT_SIZE / style.view.max_element());
+    let size = (style.view * scale).round();
+    let target = render_target(size.x as u32, size.y as u32);
+    let data = render_offscreen(target, physics_objects, style, background);
+    target.delete();
+
+    let mut still = RgbaImage::from_raw(data.width as u32, data.height as u32, data.bytes)
+        .expect("Render target data doesn't match its dimensions.");
+    // The target is read bottom-up
+    image::imageops::flip_vertical_in_place(&mut still);
+
+    fs::create_dir_all("recordings")?;
+    let path = PathBuf::from(format!("recordings/still_{}.png", unix_timestamp()));
+    still.save(&path)?;
+    Ok(path)
+}
+
+// Digits typed build up the radius, Enter applies it if it's in range and Esc cancels.
+fn handle_radius_entry(game_state: &mut GameState, mode: Mode) {
+    let Some(entry) = &mut game_state.radius_entry else {
+        return;
+    };
+    while let Some(character) = get_char_pressed() {
+        if (character.is_ascii_digit() || character == '.') && entry.len() < 6 {
+            entry.push(character);
+        }
+    }
+    if is_key_pressed(KeyCode::Backspace) {
+        entry.pop();
+    }
+
+    if is_key_pressed(KeyCode::Escape) {
+        game_state.radius_entry = None;
+    } else if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::KpEnter) {
+        match entry.parse::<f32>() {
+            Ok(radius) if (MIN_TOOL_RADIUS..=MAX_TOOL_RADIUS).contains(&radius) => {
+                *game_state.active_radius_mut(mode) = radius;
+                game_state.radius_entry = None;
+                game_state.show_notice(format!("Radius: {}", radius));
+            }
+            _ => game_state.show_notice(format!(
+                "Radius must be between {} and {}",
+                MIN_TOOL_RADIUS, MAX_TOOL_RADIUS
+            )),
+        }
+    }
+}
+
+// Logic shared by every mode.
+fn handle_shared_logic(
+    game_state: &mut GameState,
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+) {
+    // Start typing an exact radius for the active tool
+    if is_key_pressed(KeyCode::Tab) {
+        // Drop whatever was typed before, it wasn't meant for the entry
+        while get_char_pressed().is_some() {}
+        game_state.radius_entry = Some(String::new());
+    }
+
+    // Hide/show all UI
+    if is_key_pressed(KeyCode::H) {
+        game_state.show_ui = !game_state.show_ui;
+        if !game_state.show_ui {
+            game_state.show_notice("UI hidden, (H) to show".to_owned());
+        }
+    }
+
+    // Smooth particle and tether rendering
+    if is_key_pressed(KeyCode::F1) {
+        game_state.anti_aliasing = !game_state.anti_aliasing;
+    }
+
+    // Render quality: Shift for circle segments, Ctrl for tether width, plain for particle size
+    let shift_down = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+    let ctrl_down = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+    if shift_down && (is_key_pressed(KeyCode::F2) || is_key_pressed(KeyCode::F3)) {
+        game_state.circle_segments = if is_key_pressed(KeyCode::F3) {
+            game_state.circle_segments.saturating_add(1)
+        } else {
+            game_state.circle_segments.saturating_sub(1)
+        }
+        .clamp(MIN_CIRCLE_SEGMENTS, MAX_CIRCLE_SEGMENTS);
+        game_state.show_notice(format!("Circle segments: {}", game_state.circle_segments));
+    } else if ctrl_down && (is_key_pressed(KeyCode::F2) || is_key_pressed(KeyCode::F3)) {
+        let step = if is_key_pressed(KeyCode::F3) {
+            TETHER_WIDTH_STEP
+        } else {
+            -TETHER_WIDTH_STEP
+        };
+        game_state.tether_width =
+            (game_state.tether_width + step).clamp(MIN_TETHER_WIDTH, MAX_TETHER_WIDTH);
+        game_state.show_notice(format!("Tether width: {:.2}x", game_state.tether_width));
+    } else if is_key_pressed(KeyCode::F2) || is_key_pressed(KeyCode::F3) {
+        let step = if is_key_pressed(KeyCode::F3) {
+            0.5
+        } else {
+            -0.5
+        };
+        game_state.particle_render_radius = (game_state.particle_render_radius + step)
+            .clamp(MIN_PARTICLE_RENDER_RADIUS, MAX_PARTICLE_RENDER_RADIUS);
+        game_state.show_notice(format!(
+            "Particle size: {:.1}",
+            game_state.particle_render_radius
+        ));
+    }
+
+    // Background color
+    if is_key_pressed(KeyCode::F4) {
+        let next = BACKGROUND_COLORS
+            .iter()
+            .position(|&color| color == game_state.background_color)
+            .map_or(0, |i| (i + 1) % BACKGROUND_COLORS.len());
+        game_state.background_color = BACKGROUND_COLORS[next];
+        game_state.show_notice(format!("Background: #{:06X}", game_state.background_color));
+    }
+
+    // Pick up edits to the obstacle file, or with Shift the timeline
+    if is_key_pressed(KeyCode::F6)
+        && (is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift))
+    {
+        match load_timeline() {
+            Ok(timeline) => {
+                game_state.show_notice(format!("Loaded {} timeline changes", timeline.len()));
+                game_state.set_timeline(timeline);
+            }
+            Err(error) => game_state.show_notice(format!("Can't load timeline: {}", error)),
+        }
+    } else if is_key_pressed(KeyCode::F6) {
+        match load_obstacles() {
+            Ok(obstacles) => {
+                game_state.show_notice(format!("Loaded {} obstacles", obstacles.len()));
+                game_state.obstacles = Arc::new(obstacles);
+            }
+            Err(error) => game_state.show_notice(format!("Can't load obstacles: {}", error)),
+        }
+    }
+
+    // Exchange the bodies with other tools through the mesh interchange format
+    if is_key_pressed(KeyCode::F7) {
+        match fs::write(MESH_PATH, export_mesh_json(physics_objects)) {
+            Ok(()) => game_state.show_notice(format!("Exported mesh to {}", MESH_PATH)),
+            Err(error) => game_state.show_notice(format!("Can't export mesh: {}", error)),
+        }
+    }
+    if is_key_pressed(KeyCode::F8) {
+        match fs::read_to_string(MESH_PATH)
+            .map_err(|error| error.to_string())
+            .and_then(|text| import_mesh_json(&text))
+        {
+            Ok(mesh) => {
+                *physics_objects = mesh;
+                game_state.structure_version += 1;
+                game_state.built_area = None;
+                check_invariants(physics_objects, "importing a mesh");
+                game_state.sim_time = 0.;
+                game_state.sim_steps = 0;
+                game_state.selected_tether = None;
+                game_state.inspected_particle = None;
+                game_state.edit_selection = None;
+                game_state.grabbed_particle = None;
+                let imported = format!(
+                    "Imported {} particles, {} tethers",
+                    physics_objects.0.len(),
+                    physics_objects.1.len()
+                );
+                // Warn about tethers too stiff for the longest step the settings allow
+                let longest = game_state.max_dt / game_state.max_substeps as f32;
+                match critical_timestep(physics_objects, game_state.stiffness_scale())
+                    .filter(|&critical| critical < longest)
+                {
+                    Some(critical) => game_state.show_notice(format!(
+                        "{}, but they're only stable below {:.2} ms steps (up to {:.2} ms now). \
+                         Raise max_substeps to {}",
+                        imported,
+                        critical * 1000.,
+                        longest * 1000.,
+                        (game_state.max_dt / critical).ceil()
+                    )),
+                    None => game_state.show_notice(imported),
+                }
+                game_state.flush_strain_log();
+            }
+            Err(error) => game_state.show_notice(format!("Can't import mesh: {}", error)),
+        }
+    }
+
+    // Adaptive render quality and the frame rate it aims for
+    if is_key_pressed(KeyCode::F9) {
+        if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            let next = TARGET_FPS_CHOICES
+                .iter()
+                .position(|&fps| fps > game_state.target_fps)
+                .unwrap_or(0);
+            game_state.target_fps = TARGET_FPS_CHOICES[next];
+            game_state.show_notice(format!(
+                "Adaptive quality target: {:.0} fps",
+                game_state.target_fps
+            ));
+        } else {
+            game_state.adaptive_quality = !game_state.adaptive_quality;
+            game_state.quality_changed_at = get_time();
+            game_state.show_notice(format!(
+                "Adaptive quality {}",
+                if game_state.adaptive_quality {
+                    "on"
+                } else {
+                    "off"
+                }
+            ));
+        }
+    }
+
+    // Export a high resolution still of the softbody, over transparency like the recordings if set
+    if is_key_pressed(KeyCode::F5)
+        && (is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift))
+    {
+        let style = RenderStyle {
+            smooth: game_state.anti_aliasing,
+            circle_segments: MAX_CIRCLE_SEGMENTS,
+            show_tethers: true,
+            ..game_state.render_style()
+        };
+        let background = if game_state.transparent_recording {
+            Color::new(0., 0., 0., 0.)
+        } else {
+            Color::from_hex(game_state.background_color)
+        };
+        match export_still(physics_objects, style, background, EXPORT_SCALE) {
+            Ok(path) => game_state.show_notice(format!("Saved still to {}", path.display())),
+            Err(error) => game_state.show_notice(format!("Can't export still: {}", error)),
+        }
+        set_camera(&game_state.canvas_camera());
+    } else if is_key_pressed(KeyCode::F5) {
+        // Record only the softbody over transparency instead of the whole screen
+        game_state.transparent_recording = !game_state.transparent_recording;
+        game_state.show_notice(format!(
+            "Transparent recording {} (applies to the next recording)",
+            if game_state.transparent_recording {
+                "on"
+            } else {
+                "off"
+            }
+        ));
+    }
+
+    // Start/stop recording frames
+    if is_key_pressed(KeyCode::R) {
+        match game_state.recording.take() {
+            Some(recording) => game_state.show_notice(format!(
+                "Saved {} frames to {}",
+                recording.frame_count,
+                recording.directory.display()
+            )),
+            None => {
+                match Recording::start(game_state.transparent_recording, game_state.canvas_size()) {
+                    Ok(recording) => {
+                        game_state.recording = Some(recording);
+                        game_state.show_notice("Recording... (R) to stop".to_owned());
+                    }
+                    Err(error) => game_state.show_notice(format!("Can't record: {}", error)),
+                }
+            }
+        }
+    }
+
+    // Scroll sensitivity adjustment (Ctrl + -/=)
+    if is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl) {
+        let factor = if is_key_pressed(KeyCode::Equal) {
+            1.25
+        } else if is_key_pressed(KeyCode::Minus) {
+            0.8
+        } else {
+            1.
+        };
+        if factor != 1. {
+            game_state.tool_sizing_factor = (game_state.tool_sizing_factor * factor)
+                .clamp(MIN_TOOL_SIZING_FACTOR, MAX_TOOL_SIZING_FACTOR);
+            game_state.show_notice(format!(
+                "Scroll sensitivity: {:.3}",
+                game_state.tool_sizing_factor
+            ));
+        }
+    }
+}
+
+fn clear_canvas(create_canvas: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
+    imageproc::drawing::draw_filled_rect_mut(
+        create_canvas,
+        Rect::at(0, 0).of_size(create_canvas.width(), create_canvas.height()),
+        Rgba([0, 0, 0, 0]),
+    );
+}
+
+// Swap drawn and empty pixels, so the empty space gets built instead of the drawing. Partly
+// covered pixels count as drawn from half coverage up.
+fn invert_drawing(create_canvas: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, color: [u8; 4]) {
+    for pixel in create_canvas.pixels_mut() {
+        *pixel = if pixel.0[3] >= 128 {
+            Rgba([0, 0, 0, 0])
+        } else {
+            Rgba(color)
+        };
+    }
+}
+
+// Blur the drawing and snap it back to drawn/empty, which rounds off jaggies.
+// Repeating it converges since smooth edges survive the blur and threshold unchanged.
+fn smooth_drawing(create_canvas: &mut RgbaImage, scratch: &mut RgbaImage) {
+    let blurred = imageproc::filter::gaussian_blur_f32(create_canvas, SMOOTHING_SIGMA);
+    for (pixel, blurred_pixel) in scratch.pixels_mut().zip(blurred.pixels()) {
+        *pixel = snap_filtered_pixel(*blurred_pixel);
+    }
+    std::mem::swap(create_canvas, scratch);
+}
+
+// Smallest (min, max) pixel box containing every drawn pixel, if anything is drawn.
+fn drawing_bounds(
+    create_canvas: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> Option<((u32, u32), (u32, u32))> {
+    create_canvas
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| is_drawn(pixel))
+        .fold(None, |bounds, (x, y, _)| match bounds {
+            None => Some(((x, y), (x, y))),
+            Some((min, max)) => Some(((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y)))),
+        })
+}
+
+// Save the drawing, cropped to its bounds, as a new PNG in the stamp directory. Returns None if nothing is drawn.
+fn save_stamp(create_canvas: &RgbaImage) -> image::ImageResult<Option<PathBuf>> {
+    let Some((min, max)) = drawing_bounds(create_canvas) else {
+        return Ok(None);
+    };
+    let stamp = image::imageops::crop_imm(
+        create_canvas,
+        min.0,
+        min.1,
+        max.0 - min.0 + 1,
+        max.1 - min.1 + 1,
+    )
+    .to_image();
+
+    fs::create_dir_all(STAMP_DIRECTORY)?;
+    let path = PathBuf::from(format!("{}/{}.png", STAMP_DIRECTORY, unix_timestamp()));
+    stamp.save(&path)?;
+    Ok(Some(path))
+}
+
+// Every PNG in the stamp directory, by file name, with its pixels snapped to drawn/empty.
+fn load_stamps() -> std::io::Result<Vec<(String, RgbaImage)>> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(STAMP_DIRECTORY) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "png"))
+            .collect(),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(error),
+    };
+    paths.sort();
+
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| {
+            let mut stamp = image::open(&path).ok()?.to_rgba8();
+            for pixel in stamp.pixels_mut() {
+                *pixel = snap_pixel(*pixel);
+            }
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            Some((name, stamp))
+        })
+        .collect())
+}
+
+// Translate the drawing so its bounding box is centered on the canvas. Returns false if nothing is drawn.
+fn center_drawing(create_canvas: &mut RgbaImage, scratch: &mut RgbaImage) -> bool {
+    let Some((min, max)) = drawing_bounds(create_canvas) else {
+        return false;
+    };
+    let (w, h) = (create_canvas.width() as i64, create_canvas.height() as i64);
+    let (box_w, box_h) = ((max.0 - min.0 + 1) as i64, (max.1 - min.1 + 1) as i64);
+
+    // Clamp so the box is kept fully on the canvas instead of having its edges clipped
+    let new_x = ((w - box_w) / 2).clamp(0, w - box_w);
+    let new_y = ((h - box_h) / 2).clamp(0, h - box_h);
+    let (dx, dy) = (new_x - min.0 as i64, new_y - min.1 as i64);
+
+    clear_canvas(scratch);
+    for y in min.1..=max.1 {
+        for x in min.0..=max.0 {
+            let pixel = *create_canvas.get_pixel(x, y);
+            scratch.put_pixel((x as i64 + dx) as u32, (y as i64 + dy) as u32, pixel);
+        }
+    }
+    std::mem::swap(create_canvas, scratch);
+    true
+}
+
+// Resample the drawing about the center of its bounding box, shrinking the factor if the result wouldn't fit.
+// Returns false if nothing is drawn.
+fn scale_drawing(create_canvas: &mut RgbaImage, scratch: &mut RgbaImage, factor: f32) -> bool {
+    let Some((min, max)) = drawing_bounds(create_canvas) else {
+        return false;
+    };
+    let (w, h) = (create_canvas.width(), create_canvas.height());
+    let (box_w, box_h) = (max.0 - min.0 + 1, max.1 - min.1 + 1);
+    let factor = factor
+        .min(w as f32 / box_w as f32)
+        .min(h as f32 / box_h as f32);
+    let new_w = ((box_w as f32 * factor).round() as u32).clamp(1, w);
+    let new_h = ((box_h as f32 * factor).round() as u32).clamp(1, h);
+
+    let drawing = image::imageops::crop_imm(create_canvas, min.0, min.1, box_w, box_h).to_image();
+    let scaled = image::imageops::resize(
+        &drawing,
+        new_w,
+        new_h,
+        image::imageops::FilterType::Triangle,
+    );
+
+    // Keep the same center, pushed back onto the canvas if an edge would hang off
+    let center = ((min.0 + max.0) as f32 / 2., (min.1 + max.1) as f32 / 2.);
+    let new_x = ((center.0 - new_w as f32 / 2.).round() as i64).clamp(0, (w - new_w) as i64) as u32;
+    let new_y = ((center.1 - new_h as f32 / 2.).round() as i64).clamp(0, (h - new_h) as i64) as u32;
+
+    // Threshold the resampled edges back to drawn/empty
+    clear_canvas(scratch);
+    for (x, y, pixel) in scaled.enumerate_pixels() {
+        scratch.put_pixel(new_x + x, new_y + y, snap_filtered_pixel(*pixel));
+    }
+    std::mem::swap(create_canvas, scratch);
+    true
+}
+
+// Alt + 1-4 switches to a saved tool preset, Alt + Shift + 1-4 saves the mode's active tool to one.
+fn handle_tool_presets(game_state: &mut GameState, mode: Mode) {
+    if !(is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt)) {
+        return;
+    }
+    let preset_keys = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4];
+    let Some(slot) = preset_keys.iter().position(|key| is_key_pressed(*key)) else {
+        return;
+    };
+
+    if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+        let preset = game_state.current_tool_preset(mode);
+        game_state.show_notice(format!(
+            "Saved preset {}: {}, radius {:.1}",
+            slot + 1,
+            preset.name,
+            preset.radius
+        ));
+        game_state.tool_presets[slot] = Some(preset);
+    } else if let Some(preset) = game_state.tool_presets[slot].clone() {
+        game_state.apply_tool_preset(&preset);
+        let elsewhere = match (preset.tool.mode(), mode) {
+            (Mode::Create, Mode::Sim) => " (Create mode)",
+            (Mode::Sim, Mode::Create) => " (Sim mode)",
+            _ => "",
+        };
+        game_state.show_notice(format!("Preset {}: {}{}", slot + 1, preset.name, elsewhere));
+    } else {
+        game_state.show_notice(format!(
+            "Preset {} is empty, (Alt + Shift + {}) saves one",
+            slot + 1,
+            slot + 1
+        ));
+    }
+}
+
+// Make the drawing symmetric about the canvas centerline, vertical if horizontal is set. Each pixel and
+// its mirror image both take whichever of the two is more opaque, so the halves are combined.
+fn mirror_drawing(create_canvas: &mut RgbaImage, horizontal: bool) {
+    let (w, h) = create_canvas.dimensions();
+    for y in 0..h {
+        for x in 0..w {
+            let (mirror_x, mirror_y) = if horizontal {
+                (w - 1 - x, y)
+            } else {
+                (x, h - 1 - y)
+            };
+            // Visit each pair once
+            if (mirror_x, mirror_y) <= (x, y) {
+                continue;
+            }
+            let pixel = *create_canvas.get_pixel(x, y);
+            let mirrored = *create_canvas.get_pixel(mirror_x, mirror_y);
+            let kept = if pixel.0[3] >= mirrored.0[3] {
+                pixel
+            } else {
+                mirrored
+            };
+            create_canvas.put_pixel(x, y, kept);
+            create_canvas.put_pixel(mirror_x, mirror_y, kept);
+        }
+    }
+}
+
+// Write every particle's rest position and displacement from it as JSON, returning where it went.
+fn save_displacement_field(particles: &[Particle]) -> std::io::Result<PathBuf> {
+    let entries: Vec<String> = particles
+        .iter()
+        .map(|particle| {
+            let displacement = particle.position - particle.rest_position;
+            format!(
+                "{{\"rest\":[{},{}],\"displacement\":[{},{}]}}",
+                particle.rest_position.x, particle.rest_position.y, displacement.x, displacement.y
+            )
+        })
+        .collect();
+
+    fs::create_dir_all("recordings")?;
+    let path = PathBuf::from(format!("recordings/displacement_{}.json", unix_timestamp()));
+    fs::write(&path, format!("[{}]", entries.join(",")))?;
+    Ok(path)
+}
+
+fn handle_create_logic(
+    game_state: &mut GameState,
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+) {
+    // Handle brush resizing logic
+    match game_state.draw_mode {
+        DrawMode::Add => {
+            game_state.add_radius = (game_state.add_radius
+                + game_state.tool_sizing_factor * mouse_wheel().1)
+                .clamp(MIN_TOOL_RADIUS, MAX_TOOL_RADIUS)
+        }
+        DrawMode::Remove => {
+            game_state.remove_radius = (game_state.remove_radius
+                + game_state.tool_sizing_factor * mouse_wheel().1)
+                .clamp(MIN_TOOL_RADIUS, MAX_TOOL_RADIUS)
+        }
+    };
+
+    // Lattice fill, or with Shift only the newly drawn parts, added to the existing world
+    if is_key_pressed(KeyCode::Enter) {
+        let append = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+        let mut canvas = game_state.flatten_layers();
+        if game_state.build_from_empty {
+            invert_drawing(&mut canvas, DRAW_COLOR);
+        }
+        let build_start = get_time();
+        let built_area = game_state.built_area.take().filter(|_| append);
+        let mut new_area = canvas.clone();
+        if let Some(built_area) = &built_area {
+            erase_built_area(&mut new_area, built_area);
+        }
+        let built = build_lattice(&new_area, &game_state.lattice_settings());
+        game_state.show_notice(format!(
+            "{} {} particles, {} tethers in {:.1} ms",
+            if append { "Added" } else { "Built" },
+            built.0.len(),
+            built.1.len(),
+            (get_time() - build_start) * 1000.
+        ));
+        game_state.structure_version += 1;
+        if append {
+            append_physics_objects(physics_objects, built);
+        } else {
+            *physics_objects = built;
+            game_state.flush_strain_log();
+            game_state.sim_time = 0.;
+            game_state.sim_steps = 0;
+        }
+        game_state.built_area = Some(match built_area {
+            Some(mut built_area) => {
+                merge_built_area(&mut built_area, &canvas);
+                built_area
+            }
+            None => canvas,
+        });
+    }
+
+    // Toggle which side of the drawing becomes the body
+    if is_key_pressed(KeyCode::N) {
+        game_state.build_from_empty = !game_state.build_from_empty;
+    }
+
+    // Lattice type, and bracing for square lattices
+    if is_key_pressed(KeyCode::L) {
+        if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            game_state.brace_squares = !game_state.brace_squares;
+        } else {
+            game_state.lattice_type = match game_state.lattice_type {
+                LatticeType::Hexagonal => LatticeType::Square,
+                LatticeType::Square => LatticeType::Hexagonal,
+            };
+        }
+    }
+
+    // Toggle cleaning up particles left without tethers
+    if is_key_pressed(KeyCode::O) {
+        game_state.remove_orphans = !game_state.remove_orphans;
+    }
+
+    // Toggle merging coincident particles after building
+    if is_key_pressed(KeyCode::W) {
+        game_state.weld_duplicates = !game_state.weld_duplicates;
+    }
+
+    // Toggle keeping body mass independent of lattice density
+    if is_key_pressed(KeyCode::M) {
+        game_state.normalize_mass = !game_state.normalize_mass;
+        game_state.show_notice(format!(
+            "Mass normalization {}",
+            if game_state.normalize_mass {
+                "on"
+            } else {
+                "off"
+            }
+        ));
+    }
+
+    // Layer selection and visibility
+    let ctrl_down = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+    let alt_down = is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt);
+    let layer_keys = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4];
+    if let Some(layer) = layer_keys
+        .iter()
+        .position(|key| is_key_pressed(*key))
+        .filter(|_| !ctrl_down && !alt_down)
+    {
+        if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            game_state.layers[layer].visible = !game_state.layers[layer].visible;
+            game_state.canvas_dirty = true;
+        } else {
+            game_state.active_layer = layer;
+        }
+    }
+
+    // Material selection for the Add brush and fills
+    let material_keys = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+    if ctrl_down {
+        if let Some(material) = material_keys
+            .iter()
+            .take(MATERIALS.len())
+            .position(|key| is_key_pressed(*key))
+        {
+            game_state.draw_material = material;
+            game_state.show_notice(format!("Drawing with {}", MATERIALS[material].name));
+        }
+    }
+
+    // Brush switching
+    if is_key_pressed(KeyCode::Q) {
+        game_state.draw_mode = match game_state.draw_mode {
+            DrawMode::Add => DrawMode::Remove,
+            DrawMode::Remove => DrawMode::Add,
+        };
+    }
+
+    // Move the drawing to the middle of the canvas
+    if is_key_pressed(KeyCode::C) {
+        if center_drawing(
+            &mut game_state.layers[game_state.active_layer].canvas,
+            &mut game_state.scratch_canvas,
+        ) {
+            game_state.canvas_dirty = true;
+        } else {
+            game_state.show_notice("Nothing to center".to_owned());
+        }
+    }
+
+    // Invert the active layer, painting the empty space in the current material
+    if is_key_pressed(KeyCode::I) {
+        invert_drawing(
+            &mut game_state.layers[game_state.active_layer].canvas,
+            MATERIALS[game_state.draw_material].color,
+        );
+        game_state.canvas_dirty = true;
+    }
+
+    // Mirror the active layer left-right (X) or top-bottom (Y), Shift flips instead of adding the mirror
+    for (key, horizontal) in [(KeyCode::X, true), (KeyCode::Y, false)] {
+        if is_key_pressed(key) {
+            let canvas = &mut game_state.layers[game_state.active_layer].canvas;
+            if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+                if horizontal {
+                    image::imageops::flip_horizontal_in_place(canvas);
+                } else {
+                    image::imageops::flip_vertical_in_place(canvas);
+                }
+            } else {
+                mirror_drawing(canvas, horizontal);
+            }
+            game_state.canvas_dirty = true;
+        }
+    }
+
+    // Resize the drawing on the active layer about its center
+    for (key, factor) in [
+        (KeyCode::LeftBracket, 1. / DRAWING_SCALE_STEP),
+        (KeyCode::RightBracket, DRAWING_SCALE_STEP),
+    ] {
+        if !is_key_pressed(key) {
+            continue;
+        }
+        if scale_drawing(
+            &mut game_state.layers[game_state.active_layer].canvas,
+            &mut game_state.scratch_canvas,
+            factor,
+        ) {
+            game_state.canvas_dirty = true;
+        } else {
+            game_state.show_notice("Nothing to scale".to_owned());
+        }
+    }
+
+    // Save the visible drawing as a stamp, or round off jagged edges of the active layer
+    if is_key_pressed(KeyCode::S) && ctrl_down {
+        match save_stamp(&game_state.flatten_layers()) {
+            Ok(Some(path)) => game_state.show_notice(format!("Saved stamp to {}", path.display())),
+            Ok(None) => game_state.show_notice("Nothing to save as a stamp".to_owned()),
+            Err(error) => game_state.show_notice(format!("Can't save stamp: {}", error)),
+        }
+    } else if is_key_pressed(KeyCode::S) {
+        smooth_drawing(
+            &mut game_state.layers[game_state.active_layer].canvas,
+            &mut game_state.scratch_canvas,
+        );
+        game_state.canvas_dirty = true;
+    }
+
+    // Handle clear requests
+    let clear_all = is_key_pressed(KeyCode::Backspace)
+        && (is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl));
+    if is_key_pressed(KeyCode::Delete) || clear_all {
+        physics_objects.0.clear();
+        physics_objects.1.clear();
+        game_state.structure_version += 1;
+        game_state.flush_strain_log();
+        game_state.built_area = None;
+        game_state.sim_time = 0.;
+        game_state.sim_steps = 0;
+    }
+    if is_key_pressed(KeyCode::Backspace) {
+        let layers = if clear_all {
+            &mut game_state.layers[..]
+        } else {
+            &mut game_state.layers[game_state.active_layer..=game_state.active_layer]
+        };
+        for layer in layers {
+            clear_canvas(&mut layer.canvas);
+        }
+        game_state.canvas_dirty = true;
+    }
+
+    // Grid overlay and snapping
+    if is_key_pressed(KeyCode::G) {
+        if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            game_state.snap_to_grid = !game_state.snap_to_grid;
+        } else {
+            game_state.show_grid = !game_state.show_grid;
+        }
+    }
+    if is_key_pressed(KeyCode::Comma) {
+        game_state.grid_spacing =
+            (game_state.grid_spacing - GRID_SPACING_STEP).max(MIN_GRID_SPACING);
+    }
+    if is_key_pressed(KeyCode::Period) {
+        game_state.grid_spacing =
+            (game_state.grid_spacing + GRID_SPACING_STEP).min(MAX_GRID_SPACING);
+    }
+    let cursor = if game_state.snap_to_grid {
+        snap_to_grid(game_state.canvas_mouse(), game_state.grid_spacing)
+    } else {
+        game_state.canvas_mouse()
+    };
+
+    // Everything below edits the active layer
+    let create_canvas = &mut game_state.layers[game_state.active_layer].canvas;
+
+    // Do flood fill, or pick the pattern it fills with
+    if is_key_pressed(KeyCode::F)
+        && (is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift))
+    {
+        game_state.fill_pattern = match game_state.fill_pattern {
+            FillPattern::Solid => FillPattern::Checkerboard,
+            FillPattern::Checkerboard => FillPattern::Hatch,
+            FillPattern::Hatch => FillPattern::Solid,
+        };
+    } else if is_key_pressed(KeyCode::F) {
+        let (x, y) = clamp_to_canvas(create_canvas, cursor);
+        flood_fill(
+            create_canvas,
+            (x as u32, y as u32),
+            Rgba(MATERIALS[game_state.draw_material].color),
+            game_state.fill_pattern,
+        );
+        game_state.canvas_dirty = true;
+    }
+
+    // Handle drawing logic. Strokes only start with a press, so a button left stuck down by events
+    // missed while the window was in the background doesn't scribble on its own. Every press has
+    // already cleared was_drawing, so it never joins up with a stroke whose release was missed.
+    if is_mouse_button_down(MouseButton::Left)
+        && (game_state.was_drawing || is_mouse_button_pressed(MouseButton::Left))
+    {
+        if !game_state.was_drawing {
+            game_state.stroke_start = cursor;
+        }
+
+        // Holding arrow keys keeps the stroke on a straight line through its start
+        let new_pos = match arrow_direction() {
+            Some(direction) => {
+                let start = Vec2::from(game_state.stroke_start);
+                let along = (Vec2::from(cursor) - start).dot(direction);
+                (start + along * direction).into()
+            }
+            None => cursor,
+        };
+
+        if game_state.was_drawing {
+            let last_pos = game_state.last_draw_pos;
+
+            let draw_info = match game_state.draw_mode {
+                DrawMode::Add => (
+                    game_state.add_radius,
+                    Rgba(MATERIALS[game_state.draw_material].color),
+                ),
+                DrawMode::Remove => (game_state.remove_radius, Rgba([0, 0, 0, 0])),
+            };
+
+            draw_rounded_line(
+                create_canvas,
+                last_pos,
+                new_pos,
+                2. * draw_info.0,
+                draw_info.1,
+            );
+            game_state.canvas_dirty = true;
+        }
+        game_state.was_drawing = true;
+        // Update last position that was drawn to. (for filling gaps between mouse jumps)
+        game_state.last_draw_pos = new_pos;
+    }
+    if is_mouse_button_released(MouseButton::Left) {
+        game_state.was_drawing = false;
+    }
+}
+
+// Unit direction of the held arrow keys (e.g. Up + Right is the 45 degree diagonal), if any are held.
+fn arrow_direction() -> Option<Vec2> {
+    let key_axis = |negative, positive| {
+        is_key_down(positive) as i32 as f32 - is_key_down(negative) as i32 as f32
+    };
+    let direction = vec2(
+        key_axis(KeyCode::Left, KeyCode::Right),
+        key_axis(KeyCode::Up, KeyCode::Down),
+    );
+    // Left or Right alone (or Up or Down alone) still means a horizontal (or vertical) line
+    (direction != Vec2::ZERO).then(|| direction.normalize())
+}
+
+fn handle_sim_logic(
+    game_state: &mut GameState,
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+) {
+    let (mouse_x, mouse_y) = game_state.canvas_mouse();
+
+    // The particles may have been rebuilt or edited since the grid was last updated
+    if game_state.spatial_grid.particle_count != physics_objects.0.len() {
+        game_state.spatial_grid = SpatialGrid::new(&physics_objects.0, GRID_CELL_SIZE);
+    }
+
+    // Force tool resizing, or the mouse spring's stiffness while it holds a particle
+    if game_state.grabbed_particle.is_none() {
+        game_state.force_radius = (game_state.force_radius
+            + game_state.tool_sizing_factor * mouse_wheel().1)
+            .clamp(MIN_TOOL_RADIUS, MAX_TOOL_RADIUS);
+    }
+
+    // Integrator switching, or with Shift a check of the timestep against the stiffest particle, or
+    // with Ctrl a copy of the world run with the other integrator alongside it
+    if is_key_pressed(KeyCode::I) {
+        if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            let notice = timestep_report(game_state, physics_objects);
+            game_state.show_notice(notice);
+        } else if is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl) {
+            game_state.comparison = match game_state.comparison {
+                Some(_) => None,
+                None => Some(Comparison {
+                    physics_objects: physics_objects.clone(),
+                    spatial_grid: SpatialGrid::new(&physics_objects.0, GRID_CELL_SIZE),
+                }),
+            };
+        } else {
+            game_state.integrator = game_state.integrator.other();
+        }
+    }
+
+    // Switching between tether forces and constraint projection
+    if is_key_pressed(KeyCode::X) {
+        game_state.physics_mode = match game_state.physics_mode {
+            PhysicsMode::ForceBased => PhysicsMode::Pbd,
+            PhysicsMode::Pbd => PhysicsMode::ForceBased,
+        };
+    }
+    if is_key_pressed(KeyCode::D) {
+        if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            game_state.sleep_enabled = !game_state.sleep_enabled;
+            if !game_state.sleep_enabled {
+                physics_objects.0.iter_mut().for_each(Particle::wake);
+            }
+        } else {
+            game_state.background_physics = !game_state.background_physics;
+        }
+    }
+
+    // Shattering bodies on hard impacts
+    if is_key_pressed(KeyCode::Backslash) {
+        game_state.shatter_enabled = !game_state.shatter_enabled;
+    }
+    if is_key_pressed(KeyCode::Home) {
+        game_state.shatter_threshold =
+            (game_state.shatter_threshold / SHATTER_THRESHOLD_STEP).max(MIN_SHATTER_THRESHOLD);
+    }
+    if is_key_pressed(KeyCode::End) {
+        game_state.shatter_threshold =
+            (game_state.shatter_threshold * SHATTER_THRESHOLD_STEP).min(MAX_SHATTER_THRESHOLD);
+    }
+    if is_key_pressed(KeyCode::U) {
+        game_state.pbd_iterations =
+            if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+                game_state.pbd_iterations.saturating_sub(1).max(1)
+            } else {
+                (game_state.pbd_iterations + 1).min(MAX_PBD_ITERATIONS)
+            };
+    }
+
+    // Global damping, high to settle bodies down and back to zero to let them move freely again
+    if is_key_pressed(KeyCode::M) {
+        let step = if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            -GLOBAL_DAMPING_STEP
+        } else {
+            GLOBAL_DAMPING_STEP
+        };
+        game_state.global_damping =
+            (game_state.global_damping + step).clamp(0., MAX_GLOBAL_DAMPING);
+        game_state.show_notice(format!(
+            "Global damping: {:.1} /s (velocity x{:.2} per second)",
+            game_state.global_damping,
+            (-game_state.global_damping).exp()
+        ));
+    }
+
+    if is_key_pressed(KeyCode::N) {
+        game_state.collision_iterations =
+            if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+                game_state.collision_iterations.saturating_sub(1).max(1)
+            } else {
+                (game_state.collision_iterations + 1).min(MAX_COLLISION_ITERATIONS)
+            };
+    }
+
+    // Collision response switching
+    if is_key_pressed(KeyCode::C) {
+        game_state.collision_response = match game_state.collision_response {
+            CollisionResponse::Penalty => CollisionResponse::Impulse,
+            CollisionResponse::Impulse => CollisionResponse::Penalty,
+        };
+    }
+
+    // Switch the body under the cursor to the next material
+    if is_key_pressed(KeyCode::B) {
+        if let Some(index) = nearest_particle(
+            &physics_objects.0,
+            Some(&game_state.spatial_grid),
+            vec2(mouse_x, mouse_y),
+            game_state.force_radius,
+        ) {
+            let material = (physics_objects.0[index].material + 1) % MATERIALS.len();
+            for i in connected_particles(physics_objects, index) {
+                physics_objects.0[i].material = material;
+            }
+            game_state.show_notice(format!("Body material: {}", MATERIALS[material].name));
+        }
+    }
+
+    // Nudge the grabbed, linking, or inspected particle a pixel at a time (Alt + arrow keys)
+    if is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt) {
+        let nudge = [
+            (KeyCode::Left, vec2(-1., 0.)),
+            (KeyCode::Right, vec2(1., 0.)),
+            (KeyCode::Up, vec2(0., -1.)),
+            (KeyCode::Down, vec2(0., 1.)),
+        ]
+        .iter()
+        .filter(|(key, _)| is_key_pressed(*key))
+        .fold(Vec2::ZERO, |total, (_, step)| total + *step);
+
+        if let Some(particle) = game_state
+            .grabbed_particle
+            .or(game_state.edit_selection)
+            .or(game_state.inspected_particle)
+            .and_then(|i| physics_objects.0.get_mut(i))
+            .filter(|_| nudge != Vec2::ZERO)
+        {
+            particle.position += nudge;
+            particle.velocity = Vec2::ZERO;
+            let position = particle.position;
+            game_state.show_notice(format!(
+                "Particle at ({:.1}, {:.1})",
+                position.x, position.y
+            ));
+        }
+    }
+
+    // Kinetic energy graph toggling, or a one-off burst at the cursor with Shift (out) or Ctrl (in)
+    if is_key_pressed(KeyCode::E) {
+        let shift_down = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+        let ctrl_down = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+        if shift_down || ctrl_down {
+            let speed = if shift_down {
+                BURST_SPEED
+            } else {
+                -BURST_SPEED
+            };
+            let count = radial_impulse(
+                &mut physics_objects.0,
+                vec2(mouse_x, mouse_y),
+                game_state.force_radius,
+                speed,
+            );
+            game_state.show_notice(format!(
+                "{} {} particles",
+                if shift_down { "Exploded" } else { "Imploded" },
+                count
+            ));
+        } else {
+            game_state.show_energy_graph = !game_state.show_energy_graph;
+        }
+    }
+
+    // Body centroid readout toggling
+    if is_key_pressed(KeyCode::W) {
+        game_state.show_body_info = !game_state.show_body_info;
+    }
+
+    // Delaunay overlay toggling, or the tether crossing check with Shift
+    if is_key_pressed(KeyCode::L) {
+        if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            game_state.tether_crossings = match game_state.tether_crossings {
+                Some(_) => None,
+                None => Some(vec![]),
+            };
+        } else {
+            game_state.show_delaunay = !game_state.show_delaunay;
+            if !game_state.show_delaunay {
+                game_state.delaunay = None;
+            }
+        }
+    }
+    if game_state.tether_crossings.is_some() {
+        game_state.tether_crossings = Some(crossing_tethers(physics_objects));
+    }
+
+    // Displacement field toggling, or saving it with Shift
+    if is_key_pressed(KeyCode::S) {
+        if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            match save_displacement_field(&physics_objects.0) {
+                Ok(path) => game_state
+                    .show_notice(format!("Saved displacement field to {}", path.display())),
+                Err(error) => {
+                    game_state.show_notice(format!("Can't save displacement field: {}", error))
+                }
+            }
+        } else {
+            game_state.show_displacement = !game_state.show_displacement;
+        }
+    }
+
+    // Force probe toggling
+    if is_key_pressed(KeyCode::J) {
+        game_state.force_probe = !game_state.force_probe;
+    }
+
+    // Ghost capturing, clearing, and visibility
+    if is_key_pressed(KeyCode::V) {
+        if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            game_state.ghost = None;
+        } else {
+            game_state.ghost = Some(physics_objects.0.iter().map(|p| p.position).collect());
+            game_state.ghost_visible = true;
+        }
+    }
+    if is_key_pressed(KeyCode::Y) {
+        if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            game_state.show_rest_shape = !game_state.show_rest_shape;
+        } else {
+            game_state.ghost_visible = !game_state.ghost_visible;
+        }
+    }
+
+    // Tether counts per particle, redone only when the particles or tethers change
+    if is_key_pressed(KeyCode::T)
+        && (is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift))
+    {
+        game_state.show_degrees = !game_state.show_degrees;
+    }
+    if game_state.show_degrees
+        && game_state
+            .degrees
+            .as_ref()
+            .is_none_or(|(version, _)| *version != game_state.structure_version)
+    {
+        game_state.degrees = Some((
+            game_state.structure_version,
+            particle_degrees(physics_objects),
+        ));
+    }
+
+    // Tether force inspection (deselects if nothing is under the tool)
+    if is_key_pressed(KeyCode::T)
+        && !is_key_down(KeyCode::LeftShift)
+        && !is_key_down(KeyCode::RightShift)
+    {
+        game_state.selected_tether = nearest_tether(
+            physics_objects,
+            vec2(mouse_x, mouse_y),
+            game_state.force_radius,
+        );
+        game_state.force_history.clear();
+    }
+
+    // Cycle the script run after every step, saving what the last one collected
+    let ctrl_down = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+    if is_key_pressed(KeyCode::K) && ctrl_down {
+        if let StepScript::LogEnergy(samples) = &game_state.step_script {
+            match save_energy_log(samples) {
+                Ok(path) => {
+                    game_state.show_notice(format!("Saved energy log to {}", path.display()))
+                }
+                Err(error) => game_state.show_notice(format!("Can't save energy log: {}", error)),
+            }
+        }
+        game_state.step_script = game_state.step_script.next();
+        game_state.sim_halted = false;
+    }
+
+    // Strain logging of every tether, or only those under the tool
+    if is_key_pressed(KeyCode::K) && !ctrl_down {
+        match game_state.strain_log.take() {
+            Some(strain_log) => match strain_log.save() {
+                Ok(path) => {
+                    game_state.show_notice(format!("Saved strain log to {}", path.display()))
+                }
+                Err(error) => game_state.show_notice(format!("Can't save strain log: {}", error)),
+            },
+            None => {
+                let tethers = if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift)
+                {
+                    tethers_near(
+                        physics_objects,
+                        vec2(mouse_x, mouse_y),
+                        game_state.force_radius,
+                    )
+                } else {
+                    (0..physics_objects.1.len()).collect()
+                };
+                game_state.strain_log = Some(StrainLog::new(tethers));
+            }
+        }
+    }
+
+    // Heating and cooling (Ctrl + -/= is left to scroll sensitivity)
+    if !is_key_down(KeyCode::LeftControl) && !is_key_down(KeyCode::RightControl) {
+        if is_key_down(KeyCode::Equal) {
+            game_state.temperature += TEMPERATURE_RATE * get_frame_time();
+        }
+        if is_key_down(KeyCode::Minus) {
+            game_state.temperature -= TEMPERATURE_RATE * get_frame_time();
+        }
+    }
+
+    // Regenerate the tethers over the current (possibly deformed) shape, cleaned up onto a grid with Shift
+    if is_key_pressed(KeyCode::Enter) {
+        if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            quantize_positions(&mut physics_objects.0, game_state.quantize_spacing);
+        }
+        rebuild_tethers(
+            physics_objects,
+            game_state.tether_stiffness,
+            game_state.tether_damping,
+        );
+        game_state.flush_strain_log();
+        game_state.selected_tether = None;
+    }
+
+    // Thin out the lattice, keeping one particle per grid cell
+    if is_key_pressed(KeyCode::O) {
+        let before = physics_objects.0.len();
+        decimate_lattice(
+            physics_objects,
+            game_state.tether_stiffness,
+            game_state.tether_damping,
+        );
+        game_state.selected_tether = None;
+        game_state.inspected_particle = None;
+        game_state.edit_selection = None;
+        game_state.grabbed_particle = None;
+        game_state.show_notice(format!(
+            "Thinned {} particles to {}",
+            before,
+            physics_objects.0.len()
+        ));
+        game_state.structure_version += 1;
+        game_state.flush_strain_log();
+    }
+
+    // Make the current shape the rest shape, everywhere or only under the tool (Shift)
+    if is_key_pressed(KeyCode::Z) {
+        let tethers = if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            tethers_near(
+                physics_objects,
+                vec2(mouse_x, mouse_y),
+                game_state.force_radius,
+            )
+        } else {
+            (0..physics_objects.1.len()).collect()
+        };
+        relax_tethers(physics_objects, &tethers);
+        game_state.show_notice(format!("Relaxed {} tethers", tethers.len()));
+    }
+
+    // Gravity toggling
+    if is_key_pressed(KeyCode::G) {
+        game_state.gravity_enabled = !game_state.gravity_enabled;
+    }
+
+    // Anchor the top row of the body under the cursor (or every body), or release all anchors
+    if is_key_pressed(KeyCode::A) {
+        if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            physics_objects
+                .0
+                .iter_mut()
+                .for_each(|particle| particle.anchored = false);
+        } else {
+            let hovered = nearest_particle(
+                &physics_objects.0,
+                Some(&game_state.spatial_grid),
+                vec2(mouse_x, mouse_y),
+                game_state.force_radius,
+            );
+            let targets = match hovered {
+                Some(index) => vec![connected_particles(physics_objects, index)],
+                None => bodies(physics_objects),
+            };
+            for body in targets {
+                anchor_top_row(&mut physics_objects.0, &body);
+            }
+        }
+    }
+
+    // Floor toggling and moving
+    if is_key_pressed(KeyCode::F) {
+        game_state.floor_enabled = !game_state.floor_enabled;
+    }
+    if is_key_down(KeyCode::PageUp) {
+        game_state.floor_y -= FLOOR_SPEED * get_frame_time();
+    }
+    if is_key_down(KeyCode::PageDown) {
+        game_state.floor_y += FLOOR_SPEED * get_frame_time();
+    }
+    game_state.floor_y = game_state.floor_y.clamp(0., game_state.canvas_size().y);
+
+    // Particle inspection by index, wrapping around at the ends
+    let particle_count = physics_objects.0.len();
+    if particle_count > 0 {
+        if is_key_pressed(KeyCode::Period) {
+            game_state.inspected_particle = Some(match game_state.inspected_particle {
+                Some(i) => (i + 1) % particle_count,
+                None => 0,
+            });
+        }
+        if is_key_pressed(KeyCode::Comma) {
+            game_state.inspected_particle = Some(match game_state.inspected_particle {
+                Some(i) if i > 0 && i <= particle_count => i - 1,
+                _ => particle_count - 1,
+            });
+        }
+    }
+    if is_key_pressed(KeyCode::Slash) {
+        game_state.inspected_particle = None;
+    }
+
+    // Inspect the clicked particle and dump its connectivity for debugging the lattice
+    if is_mouse_button_pressed(MouseButton::Middle) {
+        if let Some(index) = nearest_particle(
+            &physics_objects.0,
+            Some(&game_state.spatial_grid),
+            vec2(mouse_x, mouse_y),
+            game_state.force_radius,
+        ) {
+            game_state.inspected_particle = Some(index);
+            let connections = tether_connections(physics_objects, index);
+            eprintln!("Particle {}: {} tethers", index, connections.len());
+            for (tether, other, length, rest) in connections {
+                eprintln!(
+                    "  tether {} -> particle {}, length {:.2}, rest length {:.2}",
+                    tether, other, length, rest
+                );
+            }
+        }
+    }
+
+    // Collision groups for the body under the cursor
+    let group_keys = [
+        KeyCode::Key0,
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+    ];
+    let alt_down = is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt);
+    if let Some(group) = group_keys
+        .iter()
+        .position(|key| is_key_pressed(*key))
+        .filter(|_| !alt_down)
+    {
+        if let Some(index) = nearest_particle(
+            &physics_objects.0,
+            Some(&game_state.spatial_grid),
+            vec2(mouse_x, mouse_y),
+            game_state.force_radius,
+        ) {
+            let body_group = physics_objects.0[index].collision_group;
+            if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+                // Flip whether this body's group and the chosen group collide
+                let collides = !game_state.group_collisions[body_group][group];
+                game_state.group_collisions[body_group][group] = collides;
+                game_state.group_collisions[group][body_group] = collides;
+                game_state.show_notice(format!(
+                    "Groups {} and {} {}",
+                    body_group,
+                    group,
+                    if collides {
+                        "collide"
+                    } else {
+                        "ignore each other"
+                    }
+                ));
+            } else {
+                for i in connected_particles(physics_objects, index) {
+                    physics_objects.0[i].set_collision_group(group);
+                }
+            }
+        }
+    }
+
+    // Tool switching
+    if is_key_pressed(KeyCode::Q) {
+        game_state.sim_tool = match game_state.sim_tool {
+            SimTool::Force => SimTool::Stiffness,
+            SimTool::Stiffness => SimTool::Edit,
+            SimTool::Edit => SimTool::Spring,
+            SimTool::Spring => SimTool::Muscle,
+            SimTool::Muscle => SimTool::Stamp,
+            SimTool::Stamp => SimTool::Anchor,
+            SimTool::Anchor => SimTool::Launch,
+            SimTool::Launch => SimTool::Force,
+        };
+        // Pick up any stamps saved since the tool was last used
+        if let SimTool::Stamp = game_state.sim_tool {
+            match load_stamps() {
+                Ok(stamps) => game_state.stamps = stamps,
+                Err(error) => game_state.show_notice(format!("Can't load stamps: {}", error)),
+            }
+            game_state.stamp_index = game_state
+                .stamp_index
+                .min(game_state.stamps.len().saturating_sub(1));
+        }
+        game_state.edit_selection = None;
+        game_state.grabbed_particle = None;
+        game_state.launch_start = None;
+    }
+
+    // Ctrl + drag sets the gravity from the drag, taking the mouse away from the tools
+    let ctrl_down = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+    if ctrl_down && is_mouse_button_pressed(MouseButton::Left) {
+        game_state.gravity_drag_start = Some(vec2(mouse_x, mouse_y));
+    }
+    if let Some(start) = game_state.gravity_drag_start {
+        if is_mouse_button_released(MouseButton::Left) {
+            game_state.gravity = (vec2(mouse_x, mouse_y) - start) * GRAVITY_DRAG_SCALE;
+            game_state.gravity_enabled = true;
+            game_state.gravity_drag_start = None;
+            game_state.show_notice(format!(
+                "Gravity: ({:.0}, {:.0})",
+                game_state.gravity.x, game_state.gravity.y
+            ));
+        }
+    }
+    let mouse_on_tools = !ctrl_down && game_state.gravity_drag_start.is_none();
+
+    // Left click pushes/stiffens, right click pulls/softens
+    let tool_direction = if !mouse_on_tools {
+        0.
+    } else if is_mouse_button_down(MouseButton::Left) {
+        1.
+    } else if is_mouse_button_down(MouseButton::Right) {
+        -1.
+    } else {
+        0.
+    };
+    if tool_direction != 0. {
+        // The comparison gets the same pushes, so only the integrators differ
+        let comparison = game_state
+            .comparison
+            .as_mut()
+            .map(|comparison| &mut comparison.physics_objects);
+        for world in std::iter::once(&mut *physics_objects).chain(comparison) {
+            match game_state.sim_tool {
+                // Force tool forcing ig
+                SimTool::Force => apply_force_from_point(
+                    world,
+                    vec2(mouse_x, mouse_y),
+                    tool_direction * FORCE_TOOL_STRENGTH * game_state.force_radius,
+                ),
+                SimTool::Stiffness => paint_stiffness(
+                    world,
+                    vec2(mouse_x, mouse_y),
+                    game_state.force_radius,
+                    STIFFNESS_PAINT_STEP.powf(tool_direction),
+                ),
+                SimTool::Edit
+                | SimTool::Spring
+                | SimTool::Muscle
+                | SimTool::Stamp
+                | SimTool::Anchor
+                | SimTool::Launch => (),
+            }
+        }
+    }
+    let point = vec2(mouse_x, mouse_y);
+    match game_state.sim_tool {
+        _ if !mouse_on_tools => (),
+        SimTool::Edit => handle_edit_tool(game_state, physics_objects, point),
+        SimTool::Spring => handle_spring_tool(game_state, physics_objects, point),
+        SimTool::Muscle => handle_muscle_tool(game_state, physics_objects, point),
+        SimTool::Stamp => handle_stamp_tool(game_state, physics_objects, point),
+        SimTool::Anchor => handle_anchor_tool(game_state, physics_objects, point),
+        SimTool::Launch => handle_launch_tool(game_state, physics_objects, point),
+        SimTool::Force | SimTool::Stiffness => (),
+    }
+
+    // Only spend extra substeps on frames where the body is strongly deformed, and none once a step
+    // script has stopped the simulation
+    let substeps = if game_state.sim_halted {
+        0
+    } else {
+        adaptive_substeps(
+            physics_objects,
+            game_state.substep_strain_threshold,
+            game_state.max_substeps,
+        )
+    };
+    // A long frame (window drag, stall) is simulated as a shorter one rather than as one huge step
+    let dt = get_frame_time().min(game_state.max_dt) / substeps.max(1) as f32;
+    if game_state.comparison.is_some() {
+        let settings = PhysicsSettings {
+            integrator: game_state.integrator.other(),
+            ..game_state.physics_settings()
+        };
+        let bounds = game_state.canvas_size();
+        if let Some(comparison) = &mut game_state.comparison {
+            for _ in 0..substeps {
+                update_physics(
+                    &mut comparison.physics_objects,
+                    &mut comparison.spatial_grid,
+                    dt,
+                    bounds,
+                    &settings,
+                );
+            }
+        }
+    }
+    if game_state.background_physics {
+        // Stepped once this frame has been drawn, see dispatch_physics. The timeline can only be
+        // checked between frames.
+        game_state.run_timeline();
+        game_state.pending_step = (substeps > 0).then_some((dt, substeps));
+    } else {
+        let mut settings = game_state.physics_settings();
+        let bounds = game_state.canvas_size();
+        for _ in 0..substeps {
+            if game_state.run_timeline() {
+                settings = game_state.physics_settings();
+            }
+            let time = game_state.sim_time + dt as f64;
+            let (broken, flow) = step_with(
+                physics_objects,
+                &mut game_state.spatial_grid,
+                dt,
+                bounds,
+                &settings,
+                |world| {
+                    game_state
+                        .step_script
+                        .after_step(world, time, settings.stiffness_scale)
+                },
+            );
+            game_state.tethers_broken(broken.len());
+            game_state.sim_time = time;
+            game_state.sim_steps += 1;
+            if let Some(strain_log) = &mut game_state.strain_log {
+                strain_log.record_step(physics_objects, &broken);
+            }
+            if let ControlFlow::Break(reason) = flow {
+                game_state.halt(reason);
+                break;
+            }
+        }
+    }
+    game_state.last_substeps = substeps;
+    game_state.average_substeps = 0.95 * game_state.average_substeps + 0.05 * substeps as f32;
+
+    // Redo the triangulation only once the particles have moved noticeably
+    if game_state.show_delaunay {
+        let stale = match &game_state.delaunay {
+            Some((positions, _)) => {
+                positions.len() != physics_objects.0.len()
+                    || positions
+                        .iter()
+                        .zip(&physics_objects.0)
+                        .any(|(old, particle)| {
+                            old.distance(particle.position) > DELAUNAY_REFRESH_DISTANCE
+                        })
+            }
+            None => true,
+        };
+        if stale {
+            let positions: Vec<Vec2> = physics_objects.0.iter().map(|p| p.position).collect();
+            let triangles = delaunay_triangles(&positions);
+            game_state.delaunay = Some((positions, triangles));
+        }
+    }
+
+    // Record the kinetic energy for the graph
+    if game_state.energy_history.len() == ENERGY_HISTORY_LEN {
+        game_state.energy_history.pop_front();
+    }
+    game_state
+        .energy_history
+        .push_back(kinetic_energy(&physics_objects.0));
+
+    // Record the force components of the inspected tether
+    if let Some(tether) = game_state
+        .selected_tether
+        .and_then(|i| physics_objects.1.get(i))
+    {
+        let p1 = physics_objects.0[tether.p1_index].position;
+        let p2 = physics_objects.0[tether.p2_index].position;
+        if game_state.force_history.len() == FORCE_HISTORY_LEN {
+            game_state.force_history.pop_front();
+        }
+        game_state
+            .force_history
+            .push_back(tether.force_components((p2 - p1).length(), game_state.stiffness_scale()));
+    }
+}
+
+// Kick the particles within radius of center straight away from it (toward it for a negative speed),
+// hardest at the center and fading to nothing at the edge. The mass-weighted mean kick is taken back
+// out, so the burst adds no net momentum and the scene as a whole stays put. Returns how many
+// particles were kicked.
+fn radial_impulse(particles: &mut [Particle], center: Vec2, radius: f32, speed: f32) -> usize {
+    let kicks: Vec<(usize, Vec2)> = particles
+        .iter()
+        .enumerate()
+        .filter(|(_, particle)| !particle.anchored)
+        .filter_map(|(i, particle)| {
+            let offset = particle.position - center;
+            let distance = offset.length();
+            (distance < radius).then(|| {
+                (
+                    i,
+                    offset.normalize_or_zero() * speed * (1. - distance / radius),
+                )
+            })
+        })
+        .collect();
+    let mass: f32 = kicks.iter().map(|&(i, _)| particles[i].mass).sum();
+    if mass <= 0. {
+        return 0;
+    }
+
+    let momentum = kicks.iter().fold(Vec2::ZERO, |total, &(i, kick)| {
+        total + kick * particles[i].mass
+    });
+    let drift = momentum / mass;
+    for &(i, kick) in &kicks {
+        particles[i].velocity += kick - drift;
+        particles[i].wake();
+    }
+    kicks.len()
+}
+
+// Anchor every particle of the body within ANCHOR_BAND of its highest particle.
+fn anchor_top_row(particles: &mut [Particle], body: &[usize]) {
+    let top = body
+        .iter()
+        .map(|&i| particles[i].position.y)
+        .fold(f32::INFINITY, f32::min);
+    for &i in body {
+        if particles[i].position.y <= top + ANCHOR_BAND {
+            particles[i].anchored = true;
+        }
+    }
+}
+
+// Number of tethers on each particle. A tether repeated between the same pair counts every time.
+fn particle_degrees(physics_objects: &(Vec<Particle>, Vec<Tether>)) -> Vec<usize> {
+    let mut degrees = vec![0; physics_objects.0.len()];
+    physics_objects.1.iter().for_each(|tether| {
+        degrees[tether.p1_index] += 1;
+        degrees[tether.p2_index] += 1;
+    });
+    degrees
+}
+
+// The tether count most particles have, which is the interior count for a lattice.
+fn most_common_degree(degrees: &[usize]) -> usize {
+    let mut counts: HashMap<usize, usize> = HashMap::new();
+    degrees
+        .iter()
+        .for_each(|&degree| *counts.entry(degree).or_default() += 1);
+    counts
+        .into_iter()
+        .max_by_key(|&(degree, count)| (count, degree))
+        .map_or(0, |(degree, _)| degree)
+}
+
+// Indices of the tethered neighbors of every particle.
+fn particle_neighbors(physics_objects: &(Vec<Particle>, Vec<Tether>)) -> Vec<Vec<usize>> {
+    let mut neighbors = vec![vec![]; physics_objects.0.len()];
+    physics_objects.1.iter().for_each(|tether| {
+        neighbors[tether.p1_index].push(tether.p2_index);
+        neighbors[tether.p2_index].push(tether.p1_index);
+    });
+    neighbors
+}
+
+// Indices of every particle in the same body as the start particle (connected through tethers).
+fn connected_particles(physics_objects: &(Vec<Particle>, Vec<Tether>), start: usize) -> Vec<usize> {
+    bodies(physics_objects)
+        .into_iter()
+        .find(|body| body.contains(&start))
+        .unwrap_or_default()
+}
+
+// Particle indices of every body (group of particles connected through tethers).
+fn bodies(physics_objects: &(Vec<Particle>, Vec<Tether>)) -> Vec<Vec<usize>> {
+    let neighbors = particle_neighbors(physics_objects);
+    let mut visited = vec![false; physics_objects.0.len()];
+    let mut bodies = vec![];
+
+    for start in 0..physics_objects.0.len() {
+        if visited[start] {
+            continue;
+        }
+        let mut body = vec![];
+        let mut frontier = vec![start];
+        visited[start] = true;
+
+        while let Some(i) = frontier.pop() {
+            body.push(i);
+            for &neighbor in &neighbors[i] {
+                if !visited[neighbor] {
+                    visited[neighbor] = true;
+                    frontier.push(neighbor);
+                }
+            }
+        }
+        bodies.push(body);
+    }
+    bodies
+}
+
+// Index of the particle closest to the point, if any is within max_distance. Equally close particles
+// go to the lowest index. The grid narrows the search when it was built from these particles, every
+// particle is checked otherwise.
+fn nearest_particle(
+    particles: &[Particle],
+    grid: Option<&SpatialGrid>,
+    point: Vec2,
+    max_distance: f32,
+) -> Option<usize> {
+    let candidates = match grid.filter(|grid| grid.particle_count == particles.len()) {
+        Some(grid) => grid.query_radius(particles, point, max_distance),
+        None => (0..particles.len()).collect(),
+    };
+    candidates
+        .into_iter()
+        .map(|i| (i, particles[i].position.distance(point)))
+        .filter(|&(_, distance)| distance <= max_distance)
+        .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)))
+        .map(|(i, _)| i)
+}
+
+// Index of the tether closest to the point, if any is within max_distance.
+fn nearest_tether(
+    physics_objects: &(Vec<Particle>, Vec<Tether>),
+    point: Vec2,
+    max_distance: f32,
+) -> Option<usize> {
+    physics_objects
+        .1
+        .iter()
+        .enumerate()
+        .map(|(i, tether)| {
+            let p1 = physics_objects.0[tether.p1_index].position;
+            let p2 = physics_objects.0[tether.p2_index].position;
+            (i, point_segment_distance(point, p1, p2))
+        })
+        .filter(|(_, distance)| *distance <= max_distance)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| i)
+}
+
+fn point_segment_distance(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    point.distance(closest_point_on_segment(point, a, b))
+}
+
+fn closest_point_on_segment(point: Vec2, a: Vec2, b: Vec2) -> Vec2 {
+    let ab = b - a;
+    let t = if ab.length_squared() > 0. {
+        ((point - a).dot(ab) / ab.length_squared()).clamp(0., 1.)
+    } else {
+        0.
+    };
+    a + ab * t
+}
+
+// Even-odd rule: a ray from the point crosses the boundary an odd number of times if it's inside.
+fn point_in_polygon(point: Vec2, polygon: &[Vec2]) -> bool {
+    let mut inside = false;
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+// Obstacle polygons from OBSTACLE_PATH, one per line as space separated `x,y` vertices. Blank lines
+// and lines starting with # are skipped. A missing file means no obstacles.
+fn load_obstacles() -> std::io::Result<Vec<Vec<Vec2>>> {
+    let text = match fs::read_to_string(OBSTACLE_PATH) {
+        Ok(text) => text,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(error),
+    };
+    let invalid = |line: usize, reason: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} line {}: {}", OBSTACLE_PATH, line + 1, reason),
+        )
+    };
+
+    let mut obstacles = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let polygon = line
+            .split_whitespace()
+            .map(|vertex| {
+                let (x, y) = vertex.split_once(',')?;
+                Some(vec2(x.parse().ok()?, y.parse().ok()?))
+            })
+            .collect::<Option<Vec<Vec2>>>()
+            .ok_or_else(|| invalid(i, "expected vertices like 10,20"))?;
+        if polygon.len() < 3 {
+            return Err(invalid(i, "a polygon needs at least 3 vertices"));
+        }
+        obstacles.push(polygon);
+    }
+    Ok(obstacles)
+}
+
+// A setting the timeline changes.
+#[derive(Clone, Copy)]
+enum TimelineChange {
+    Gravity(Vec2), // Also turns gravity on
+    GravityEnabled(bool),
+    Wind(Vec2),
+    Damping(f32),
+}
+
+impl TimelineChange {
+    fn describe(&self) -> String {
+        match self {
+            TimelineChange::Gravity(gravity) => {
+                format!("gravity ({:.0}, {:.0})", gravity.x, gravity.y)
+            }
+            TimelineChange::GravityEnabled(true) => "gravity on".to_owned(),
+            TimelineChange::GravityEnabled(false) => "gravity off".to_owned(),
+            TimelineChange::Wind(wind) => format!("wind ({:.0}, {:.0})", wind.x, wind.y),
+            TimelineChange::Damping(damping) => format!("damping {:.1}", damping),
+        }
+    }
+}
+
+struct TimelineEvent {
+    time: f64, // Sim time in seconds
+    change: TimelineChange,
+}
+
+// Read the timeline, one change per line as a time in seconds followed by the setting, e.g.
+// "2 gravity 0 980", "3.5 gravity off", "5 wind 200 0" or "8 damping 2". Blank lines and lines
+// starting with # are ignored. A missing file is an empty timeline.
+fn load_timeline() -> std::io::Result<Vec<TimelineEvent>> {
+    let text = match fs::read_to_string(TIMELINE_PATH) {
+        Ok(text) => text,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(error),
+    };
+    let invalid = |line: usize, reason: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} line {}: {}", TIMELINE_PATH, line + 1, reason),
+        )
+    };
+
+    let mut timeline = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let time = match words[0].trim_end_matches('s').parse::<f64>() {
+            Ok(time) if time >= 0. => time,
+            _ => return Err(invalid(i, "expected a time in seconds first")),
+        };
+        let vector = || -> Option<Vec2> {
+            match words[2..] {
+                [x, y] => Some(vec2(x.parse().ok()?, y.parse().ok()?)),
+                _ => None,
+            }
+        };
+        let change = match words.get(1..) {
+            Some(["gravity", "on"]) => Some(TimelineChange::GravityEnabled(true)),
+            Some(["gravity", "off"]) => Some(TimelineChange::GravityEnabled(false)),
+            Some(["gravity", ..]) => vector().map(TimelineChange::Gravity),
+            Some(["wind", ..]) => vector().map(TimelineChange::Wind),
+            Some(["damping", damping]) => damping.parse().ok().map(TimelineChange::Damping),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            invalid(
+                i,
+                "expected gravity X Y, gravity on/off, wind X Y or damping D after the time",
+            )
+        })?;
+        timeline.push(TimelineEvent { time, change });
+    }
+    timeline.sort_by(|a, b| a.time.total_cmp(&b.time));
+    Ok(timeline)
+}
+
+// [ and ] choose a stamp, left click builds it as a new body centered on the point.
+fn handle_stamp_tool(
+    game_state: &mut GameState,
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    point: Vec2,
+) {
+    let count = game_state.stamps.len();
+    if count == 0 {
+        return;
+    }
+    if is_key_pressed(KeyCode::RightBracket) {
+        game_state.stamp_index = (game_state.stamp_index + 1) % count;
+    } else if is_key_pressed(KeyCode::LeftBracket) {
+        game_state.stamp_index = (game_state.stamp_index + count - 1) % count;
+    }
+
+    if is_mouse_button_pressed(MouseButton::Left) {
+        let (_, stamp) = &game_state.stamps[game_state.stamp_index];
+        let mut body = build_lattice(stamp, &game_state.lattice_settings());
+        let offset = point - vec2(stamp.width() as f32, stamp.height() as f32) / 2.;
+        body.0.iter_mut().for_each(|particle| {
+            particle.position += offset;
+            particle.rest_position += offset;
+        });
+        append_physics_objects(physics_objects, body);
+        game_state.structure_version += 1;
+    }
+}
+
+// Left click two particles to link them with a tether, right click a tether to delete it.
+fn handle_edit_tool(
+    game_state: &mut GameState,
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    point: Vec2,
+) {
+    if is_mouse_button_pressed(MouseButton::Left) {
+        let Some(clicked) = nearest_particle(
+            &physics_objects.0,
+            Some(&game_state.spatial_grid),
+            point,
+            game_state.force_radius,
+        ) else {
+            game_state.edit_selection = None;
+            return;
+        };
+        let Some(first) = game_state
+            .edit_selection
+            .filter(|i| *i < physics_objects.0.len())
+        else {
+            game_state.edit_selection = Some(clicked);
+            return;
+        };
+
+        let exists = physics_objects.1.iter().any(|tether| {
+            (tether.p1_index, tether.p2_index) == (first, clicked)
+                || (tether.p1_index, tether.p2_index) == (clicked, first)
+        });
+        if first == clicked || exists {
+            game_state.show_notice("Those particles can't be linked".to_owned());
+        } else {
+            physics_objects.1.push(Tether::new(
+                first,
+                clicked,
+                game_state.tether_stiffness,
+                game_state.tether_damping,
+                &physics_objects.0,
+            ));
+            game_state.structure_version += 1;
+            check_invariants(physics_objects, "linking two particles");
+        }
+        game_state.edit_selection = None;
+    }
+
+    if is_mouse_button_pressed(MouseButton::Right) {
+        if let Some(index) = nearest_tether(physics_objects, point, game_state.force_radius) {
+            physics_objects.1.remove(index);
+            game_state.structure_version += 1;
+            // Tether indices have shifted
+            game_state.selected_tether = None;
+            if let Some(strain_log) = &mut game_state.strain_log {
+                strain_log.tethers_removed(&[index]);
+            }
+            check_invariants(physics_objects, "deleting a tether");
+        }
+    }
+}
+
+// Hold left click near a particle to pull it toward the cursor with a spring.
+fn handle_spring_tool(
+    game_state: &mut GameState,
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    point: Vec2,
+) {
+    let wheel = mouse_wheel().1;
+    let factor = if is_key_pressed(KeyCode::RightBracket) {
+        1.25
+    } else if is_key_pressed(KeyCode::LeftBracket) {
+        0.8
+    } else if game_state.grabbed_particle.is_some() && wheel != 0. {
+        MOUSE_SPRING_SCROLL_STEP.powf(wheel.signum())
+    } else {
+        1.
+    };
+    if factor != 1. {
+        game_state.mouse_spring_stiffness = (game_state.mouse_spring_stiffness * factor)
+            .clamp(MIN_MOUSE_SPRING_STIFFNESS, MAX_MOUSE_SPRING_STIFFNESS);
+        game_state.show_notice(format!(
+            "Spring stiffness: {:.0}",
+            game_state.mouse_spring_stiffness
+        ));
+    }
+
+    if is_mouse_button_pressed(MouseButton::Left) {
+        game_state.grabbed_particle = nearest_particle(
+            &physics_objects.0,
+            Some(&game_state.spatial_grid),
+            point,
+            game_state.force_radius,
+        );
+    }
+    if !is_mouse_button_down(MouseButton::Left) {
+        game_state.grabbed_particle = None;
+    }
+
+    if let Some(particle) = game_state
+        .grabbed_particle
+        .and_then(|i| physics_objects.0.get_mut(i))
+    {
+        let stretch = point - particle.position;
+        particle.apply_force(game_state.mouse_spring_stiffness * stretch);
+    }
+}
+
+// Left click a tether to make it a muscle with the current settings, right click to relax it.
+fn handle_muscle_tool(
+    game_state: &mut GameState,
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    point: Vec2,
+) {
+    let muscle = &mut game_state.muscle;
+    let mut changed = true;
+    if is_key_pressed(KeyCode::RightBracket) {
+        muscle.amplitude = (muscle.amplitude + 0.05).min(0.9);
+    } else if is_key_pressed(KeyCode::LeftBracket) {
+        muscle.amplitude = (muscle.amplitude - 0.05).max(0.05);
+    } else if is_key_pressed(KeyCode::Apostrophe) {
+        muscle.frequency *= 1.25;
+    } else if is_key_pressed(KeyCode::Semicolon) {
+        muscle.frequency *= 0.8;
+    } else if is_key_pressed(KeyCode::P) {
+        muscle.phase = (muscle.phase + std::f32::consts::FRAC_PI_4) % std::f32::consts::TAU;
+    } else {
+        changed = false;
+    }
+    if changed {
+        let text = format!(
+            "Muscle: amplitude {:.2}, frequency {:.2} Hz, phase {:.0} deg",
+            muscle.amplitude,
+            muscle.frequency,
+            muscle.phase.to_degrees()
+        );
+        game_state.show_notice(text);
+    }
+
+    let clicked = if is_mouse_button_pressed(MouseButton::Left) {
+        Some(true)
+    } else if is_mouse_button_pressed(MouseButton::Right) {
+        Some(false)
+    } else {
+        None
+    };
+    if let Some(make_muscle) = clicked {
+        if let Some(index) = nearest_tether(physics_objects, point, game_state.force_radius) {
+            let tether = &mut physics_objects.1[index];
+            tether.set_actuator(make_muscle.then_some(game_state.muscle));
+        }
+    }
+}
+
+// Drag to anchor every particle the tool passes over, Shift + drag or right drag to release them.
+fn handle_anchor_tool(
+    game_state: &mut GameState,
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    point: Vec2,
+) {
+    let left = is_mouse_button_down(MouseButton::Left);
+    let right = is_mouse_button_down(MouseButton::Right);
+    if !left && !right {
+        game_state.anchor_stroke_end = None;
+        return;
+    }
+    let release = right || is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+
+    // Sweep from last frame's position so fast drags don't skip particles
+    let from = game_state.anchor_stroke_end.unwrap_or(point);
+    physics_objects
+        .0
+        .iter_mut()
+        .filter(|particle| {
+            point_segment_distance(particle.position, from, point) <= game_state.force_radius
+        })
+        .for_each(|particle| {
+            particle.anchored = !release;
+            particle.velocity = Vec2::ZERO;
+        });
+    game_state.anchor_stroke_end = Some(point);
+}
+
+// Press on a body and drag out an arrow, on release the whole body moves at a velocity proportional to it.
+fn handle_launch_tool(
+    game_state: &mut GameState,
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    point: Vec2,
+) {
+    if is_mouse_button_pressed(MouseButton::Left) {
+        game_state.launch_start = nearest_particle(
+            &physics_objects.0,
+            Some(&game_state.spatial_grid),
+            point,
+            game_state.force_radius,
+        )
+        .map(|index| (index, point));
+    }
+    if is_mouse_button_pressed(MouseButton::Right) {
+        game_state.launch_start = None;
+    }
+    if !is_mouse_button_released(MouseButton::Left) {
+        return;
+    }
+    let Some((index, start)) = game_state
+        .launch_start
+        .take()
+        .filter(|(index, _)| *index < physics_objects.0.len())
+    else {
+        return;
+    };
+
+    let velocity = (point - start) * LAUNCH_VELOCITY_SCALE;
+    let body = connected_particles(physics_objects, index);
+    for &i in &body {
+        let particle = &mut physics_objects.0[i];
+        if !particle.anchored {
+            particle.velocity = velocity;
+        }
+    }
+    game_state.show_notice(format!(
+        "Launched {} particles at ({:.0}, {:.0}) px/s",
+        body.len(),
+        velocity.x,
+        velocity.y
+    ));
+}
+
+// Scale the stiffness of every tether passing within radius of the point.
+fn paint_stiffness(
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    point: Vec2,
+    radius: f32,
+    factor: f32,
+) {
+    let particles = &physics_objects.0;
+    physics_objects.1.iter_mut().for_each(|tether| {
+        let p1 = particles[tether.p1_index].position;
+        let p2 = particles[tether.p2_index].position;
+        if point_segment_distance(point, p1, p2) <= radius {
+            tether.k = (tether.k * factor).clamp(MIN_TETHER_STIFFNESS, MAX_TETHER_STIFFNESS);
+        }
+    });
+}
+
+fn apply_force_from_point(
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    point: Vec2,
+    strength: f32,
+) {
+    physics_objects.0.iter_mut().for_each(|particle| {
+        let distance = (particle.position - point).length();
+        let direction = (particle.position - point).normalize();
+        particle.apply_force(strength * direction / distance.powi(2));
+    });
+}
+
+// Net force the force tool would apply to the particles from the point, without applying it.
+fn probe_force(particles: &[Particle], point: Vec2, strength: f32) -> Vec2 {
+    particles
+        .iter()
+        .map(|particle| {
+            let offset = particle.position - point;
+            let distance = offset.length();
+            if distance == 0. {
+                Vec2::ZERO
+            } else {
+                strength * offset / distance.powi(3)
+            }
+        })
+        .fold(Vec2::ZERO, |net, force| net + force)
+}
+
+// Indices of the tethers passing within radius of the point.
+fn tethers_near(
+    physics_objects: &(Vec<Particle>, Vec<Tether>),
+    point: Vec2,
+    radius: f32,
+) -> Vec<usize> {
+    let particles = &physics_objects.0;
+    physics_objects
+        .1
+        .iter()
+        .enumerate()
+        .filter(|(_, tether)| {
+            point_segment_distance(
+                point,
+                particles[tether.p1_index].position,
+                particles[tether.p2_index].position,
+            ) <= radius
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+// Set the tethers' rest lengths to their current lengths and stop their particles so they settle there.
+fn relax_tethers(physics_objects: &mut (Vec<Particle>, Vec<Tether>), tethers: &[usize]) {
+    for &i in tethers {
+        let tether = &mut physics_objects.1[i];
+        let p1 = &physics_objects.0[tether.p1_index];
+        let p2 = &physics_objects.0[tether.p2_index];
+        let length = p1.position.distance(p2.position);
+        if length == 0. {
+            continue; // The force law needs a nonzero rest length
+        }
+        tether.initial_dist = length;
+        if let Some(actuator) = &mut tether.actuator {
+            actuator.rest_length = length;
+        }
+    }
+    for &i in tethers {
+        let (p1_index, p2_index) = (physics_objects.1[i].p1_index, physics_objects.1[i].p2_index);
+        physics_objects.0[p1_index].velocity = Vec2::ZERO;
+        physics_objects.0[p2_index].velocity = Vec2::ZERO;
+    }
+}
+
+// Round every particle's position to the nearest point of a grid, stopping it there.
+fn quantize_positions(particles: &mut [Particle], spacing: f32) {
+    for particle in particles {
+        particle.position = (particle.position / spacing).round() * spacing;
+        particle.velocity = Vec2::ZERO;
+    }
+}
+
+// Replace every tether with a new one between the same particles, at rest in their current positions.
+fn rebuild_tethers(
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    stiffness: f32,
+    damping_constant: f32,
+) {
+    let particles = &physics_objects.0;
+    physics_objects.1 = physics_objects
+        .1
+        .iter()
+        .map(|tether| {
+            let mut rebuilt = Tether::new(
+                tether.p1_index,
+                tether.p2_index,
+                stiffness,
+                damping_constant,
+                particles,
+            );
+            if rebuilt.initial_dist == 0. {
+                rebuilt.initial_dist = tether.initial_dist; // The force law needs a nonzero rest length
+            }
+            rebuilt
+        })
+        .collect();
+    // The current shape is the new rest shape
+    physics_objects
+        .0
+        .iter_mut()
+        .for_each(|particle| particle.rest_position = particle.position);
+    check_invariants(physics_objects, "rebuilding tethers");
+}
+
+// Keep one particle per grid cell a few tethers wide, then tether every survivor to the other survivors
+// of its body within reach, at rest in their current positions.
+fn decimate_lattice(
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    stiffness: f32,
+    damping_constant: f32,
+) {
+    if physics_objects.1.is_empty() {
+        return;
+    }
+    let average_length = physics_objects
+        .1
+        .iter()
+        .map(|tether| {
+            physics_objects.0[tether.p1_index]
+                .position
+                .distance(physics_objects.0[tether.p2_index].position)
+        })
+        .sum::<f32>()
+        / physics_objects.1.len() as f32;
+    let spacing = average_length * DECIMATION_SPACING;
+
+    // Which body each particle belongs to, so separate bodies aren't tethered together
+    let mut body_of = vec![0; physics_objects.0.len()];
+    for (body_index, body) in bodies(physics_objects).iter().enumerate() {
+        for &i in body {
+            body_of[i] = body_index;
+        }
+    }
+
+    // First particle of each body found in each cell survives
+    let mut occupied: HashMap<(usize, i32, i32), usize> = HashMap::new();
+    for (i, particle) in physics_objects.0.iter().enumerate() {
+        let cell = (
+            body_of[i],
+            (particle.position.x / spacing).floor() as i32,
+            (particle.position.y / spacing).floor() as i32,
+        );
+        occupied.entry(cell).or_insert(i);
+    }
+    let mut survives = vec![false; physics_objects.0.len()];
+    occupied.into_values().for_each(|i| survives[i] = true);
+
+    let mut particles = std::mem::take(&mut physics_objects.0);
+    let mut i = 0;
+    particles.retain(|_| {
+        i += 1;
+        survives[i - 1]
+    });
+    let survivor_bodies: Vec<usize> = (0..survives.len())
+        .filter(|&i| survives[i])
+        .map(|i| body_of[i])
+        .collect();
+
+    // Neighboring cells are up to a diagonal apart, so reach a little past that
+    let grid = SpatialGrid::new(&particles, spacing);
+    let mut tethers = vec![];
+    for (i, particle) in particles.iter().enumerate() {
+        for j in grid.query_radius(&particles, particle.position, spacing * 1.5) {
+            if j > i && survivor_bodies[i] == survivor_bodies[j] {
+                tethers.push(Tether::new(i, j, stiffness, damping_constant, &particles));
+            }
+        }
+    }
+    *physics_objects = (particles, tethers);
+    check_invariants(physics_objects, "thinning the lattice");
+}
+
+// Remove particles no tether refers to, remapping the tether indices to match. Returns how many were removed.
+fn remove_orphan_particles(physics_objects: &mut (Vec<Particle>, Vec<Tether>)) -> usize {
+    let mut tethered = vec![false; physics_objects.0.len()];
+    physics_objects.1.iter().for_each(|tether| {
+        tethered[tether.p1_index] = true;
+        tethered[tether.p2_index] = true;
+    });
+
+    // New index of every particle that is kept
+    let mut new_indices = vec![usize::MAX; physics_objects.0.len()];
+    let mut kept = 0;
+    for (i, is_tethered) in tethered.iter().enumerate() {
+        if *is_tethered {
+            new_indices[i] = kept;
+            kept += 1;
+        }
+    }
+
+    let removed = physics_objects.0.len() - kept;
+    let mut i = 0;
+    physics_objects.0.retain(|_| {
+        i += 1;
+        tethered[i - 1]
+    });
+    physics_objects.1.iter_mut().for_each(|tether| {
+        tether.p1_index = new_indices[tether.p1_index];
+        tether.p2_index = new_indices[tether.p2_index];
+    });
+    removed
+}
+
+// Merge particles within WELD_DISTANCE of each other into the first of them, which takes their combined
+// mass and momentum. Tethers are moved onto the merged particle, and any that end up joining a particle
+// to itself or repeating another tether are dropped. Returns the number of particles merged away.
+fn weld_duplicate_particles(physics_objects: &mut (Vec<Particle>, Vec<Tether>)) -> usize {
+    let count = physics_objects.0.len();
+    let grid = SpatialGrid::new(&physics_objects.0, GRID_CELL_SIZE);
+
+    // Index of the particle each one is merged into (itself if it's kept)
+    let mut target: Vec<usize> = (0..count).collect();
+    for i in 0..count {
+        if target[i] != i {
+            continue;
+        }
+        for j in grid.query_radius(
+            &physics_objects.0,
+            physics_objects.0[i].position,
+            WELD_DISTANCE,
+        ) {
+            if j > i && target[j] == j {
+                target[j] = i;
+            }
+        }
+    }
+    if target.iter().enumerate().all(|(i, &t)| i == t) {
+        return 0;
+    }
+
+    for (j, &i) in target.iter().enumerate() {
+        if i != j {
+            let (kept, merged) = particle_pair_mut(&mut physics_objects.0, i, j);
+            let mass = kept.mass + merged.mass;
+            kept.velocity = (kept.mass * kept.velocity + merged.mass * merged.velocity) / mass;
+            kept.mass = mass;
+            kept.anchored |= merged.anchored;
+        }
+    }
+
+    // New index of every particle that is kept
+    let mut new_indices = vec![usize::MAX; count];
+    let mut kept = 0;
+    for i in 0..count {
+        if target[i] == i {
+            new_indices[i] = kept;
+            kept += 1;
+        }
+    }
+    let mut i = 0;
+    physics_objects.0.retain(|_| {
+        i += 1;
+        target[i - 1] == i - 1
+    });
+
+    let mut seen = HashSet::new();
+    physics_objects.1.retain_mut(|tether| {
+        tether.p1_index = new_indices[target[tether.p1_index]];
+        tether.p2_index = new_indices[target[tether.p2_index]];
+        let pair = (
+            tether.p1_index.min(tether.p2_index),
+            tether.p1_index.max(tether.p2_index),
+        );
+        tether.p1_index != tether.p2_index && seen.insert(pair)
+    });
+    count - kept
+}
+
+// The first broken structural invariant of the world, if any: every tether joins two different existing
+// particles, no pair of particles is joined twice, and every rest length is positive.
+fn invariant_violation(physics_objects: &(Vec<Particle>, Vec<Tether>)) -> Option<String> {
+    let count = physics_objects.0.len();
+    let mut joined = HashSet::new();
+    for (i, tether) in physics_objects.1.iter().enumerate() {
+        let (p1, p2) = (tether.p1_index, tether.p2_index);
+        if p1 >= count || p2 >= count {
+            return Some(format!(
+                "tether {} joins particles {} and {}, but there are only {}",
+                i, p1, p2, count
+            ));
+        }
+        if p1 == p2 {
+            return Some(format!("tether {} joins particle {} to itself", i, p1));
+        }
+        if !joined.insert((p1.min(p2), p1.max(p2))) {
+            return Some(format!(
+                "tether {} joins particles {} and {}, which are already joined",
+                i, p1, p2
+            ));
+        }
+        if tether.initial_dist.is_nan() || tether.initial_dist <= 0. {
+            return Some(format!(
+                "tether {} has rest length {}",
+                i, tether.initial_dist
+            ));
+        }
+    }
+    None
+}
+
+// Panic in debug builds if an edit left the world broken, so index and geometry bugs fail where they
+// happen instead of corrupting the simulation later. Does nothing in release builds.
+fn check_invariants(physics_objects: &(Vec<Particle>, Vec<Tether>), edit: &str) {
+    if cfg!(debug_assertions) {
+        if let Some(violation) = invariant_violation(physics_objects) {
+            panic!("World broken after {}: {}", edit, violation);
+        }
+    }
+}
+
+// Add another set of physics objects, shifting its tether indices past the existing particles.
+fn append_physics_objects(
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    other: (Vec<Particle>, Vec<Tether>),
+) {
+    let offset = physics_objects.0.len();
+    physics_objects.0.extend(other.0);
+    physics_objects
+        .1
+        .extend(other.1.into_iter().map(|tether| Tether {
+            p1_index: tether.p1_index + offset,
+            p2_index: tether.p2_index + offset,
+            ..tether
+        }));
+    check_invariants(physics_objects, "appending physics objects");
+}
+
+// Erase the parts of the canvas that are drawn in the built area, leaving what hasn't been built yet.
+fn erase_built_area(create_canvas: &mut RgbaImage, built_area: &RgbaImage) {
+    for (pixel, built) in create_canvas.pixels_mut().zip(built_area.pixels()) {
+        if is_drawn(built) {
+            *pixel = Rgba([0, 0, 0, 0]);
+        }
+    }
+}
+
+// Add the canvas's drawn pixels to the built area.
+fn merge_built_area(built_area: &mut RgbaImage, create_canvas: &RgbaImage) {
+    for (built, pixel) in built_area.pixels_mut().zip(create_canvas.pixels()) {
+        if is_drawn(pixel) {
+            *built = *pixel;
+        }
+    }
+}
+
+// Build a fresh set of physics objects from an image, without needing a window or any screen state.
+fn build_lattice(
+    create_canvas: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    settings: &LatticeSettings,
+) -> (Vec<Particle>, Vec<Tether>) {
+    let mut physics_objects = (vec![], vec![]);
+    match settings.lattice_type {
+        LatticeType::Hexagonal => create_particle_lattice(
+            create_canvas,
+            &mut physics_objects,
+            settings.spacing,
+            settings.stiffness,
+            settings.damping,
+        ),
+        LatticeType::Square => create_square_lattice(
+            create_canvas,
+            &mut physics_objects,
+            settings.spacing,
+            settings.brace_squares,
+            settings.stiffness,
+            settings.damping,
+        ),
+    }
+
+    if settings.remove_orphans {
+        remove_orphan_particles(&mut physics_objects);
+    }
+
+    // Keep each body's mass the same no matter how many particles make it up
+    if let Some(total_mass) = settings.total_mass {
+        for body in bodies(&physics_objects) {
+            let particle_mass = total_mass / body.len() as f32;
+            for i in body {
+                physics_objects.0[i].mass = particle_mass;
+            }
+        }
+    }
+
+    if settings.weld {
+        weld_duplicate_particles(&mut physics_objects);
+    }
+    check_invariants(&physics_objects, "building a lattice");
+    physics_objects
+}
+
+// Square cells whose centers are drawn, with a particle on every corner and a tether along every
+// edge. Neighboring cells share their corners and edges.
+fn create_square_lattice(
+    create_canvas: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    spacing: f32,
+    braced: bool, // Tether both diagonals too, so the cells can't shear flat
+    stiffness: f32,
+    damping_constant: f32,
+) {
+    let count_x = (create_canvas.width() as f32 / spacing) as usize;
+    let count_y = (create_canvas.height() as f32 / spacing) as usize;
+
+    // Particle at each grid corner, placed by the first cell that uses it
+    let mut corners: Vec<Option<usize>> = vec![None; (count_x + 1) * (count_y + 1)];
+    let mut created_tethers: HashSet<(usize, usize)> = HashSet::new();
+    for row_i in 0..count_y {
+        for column_i in 0..count_x {
+            let x = (column_i as f32 + 0.5) * spacing;
+            let y = (row_i as f32 + 0.5) * spacing;
+            let Some(material) = material_at(create_canvas.get_pixel(x as u32, y as u32)) else {
+                continue;
+            };
+
+            // topleft-topright-bottomright-bottomleft particles for this cell
+            let cell = [(0, 0), (1, 0), (1, 1), (0, 1)].map(|(dx, dy)| {
+                let (corner_x, corner_y) = (column_i + dx, row_i + dy);
+                *corners[corner_y * (count_x + 1) + corner_x].get_or_insert_with(|| {
+                    let position = vec2(corner_x as f32 * spacing, corner_y as f32 * spacing);
+                    let mut particle = Particle::new(position, Vec2::ZERO, 1.);
+                    particle.material = material;
+                    physics_objects.0.push(particle);
+                    physics_objects.0.len() - 1
+                })
+            });
+
+            let mut edges = vec![
+                (cell[0], cell[1]),
+                (cell[1], cell[2]),
+                (cell[2], cell[3]),
+                (cell[3], cell[0]),
+            ];
+            if braced {
+                edges.extend([(cell[0], cell[2]), (cell[1], cell[3])]);
+            }
+            for (a, b) in edges {
+                if created_tethers.insert((a.min(b), a.max(b))) {
+                    physics_objects.1.push(Tether::new(
+                        a,
+                        b,
+                        stiffness,
+                        damping_constant,
+                        &physics_objects.0,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+fn create_particle_lattice(
+    create_canvas: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    hex_radius: f32,
+    stiffness: f32,
+    damping_constant: f32,
+) {
+    // Get a vector of valid centerpoints for hexagons in the lattice.
+    let dx = hex_radius * 3.;
+    let dy = hex_radius * 3.0_f32.sqrt() / 2.;
+    let count_x = ((create_canvas.width() as f32 - 1.) / dx) as u32;
+    let count_y = ((create_canvas.height() as f32 - 1.) / dy) as u32;
+
+    // Create grid of slots that may or may not be hexagons
+    let mut hex_points: Vec<Option<(f32, f32, usize)>> = vec![None; (count_x * count_y) as usize];
+
+    // Fill slots with hexagons with their location in tuple form
+    for row_i in 0..count_y {
+        let left_pad = (3. / 2.) * hex_radius * (row_i % 2) as f32;
+        for column_i in 0..count_x {
+            let x = left_pad + dx * column_i as f32;
+            let y = dy * row_i as f32;
+
+            if let Some(material) = material_at(create_canvas.get_pixel(x as u32, y as u32)) {
+                hex_points[(row_i * count_x + column_i) as usize] = Some((x, y, material));
+            }
+        }
+    }
+
+    // Create particles for each hexagon vertex, avoiding duplicate particles
+
+    // indices to topleft-topright-midright-bottomright-bottomleft-midleft particles for every placed hex
+    let mut hex_particles_indices: Vec<Option<[usize; 6]>> =
+        vec![None; (count_x * count_y) as usize];
+
+    let cos60 = 1. / 2.;
+    let sin60 = 3.0_f32.sqrt() / 2.;
+
+    hex_points.iter().enumerate().for_each(|(i, hex_point)| {
+        if hex_point.is_none() {
+            return;
+        } // Disregard if no hex in this spot
+        let (x, y, material) = hex_point.unwrap();
+        let first_new_particle = physics_objects.0.len();
+
+        // Index of the hex to the top-left of this hex. Rows don't wrap, so the first column of an
+        // unpadded row has none.
+        let column = i as u32 % count_x;
+        let left_hex_index = match ((i as f32) / (count_x as f32)).floor() as i32 % 2 == 0 {
+            true => {
+                if i as u32 >= count_x + 1 && column > 0 {
+                    i - (count_x + 1) as usize
+                } else {
+                    usize::MAX
+                }
+            }
+            false => {
+                if i as u32 >= count_x {
+                    i - count_x as usize
+                } else {
+                    usize::MAX
+                }
+            }
+        };
+        // Index of the hex to the top-right of this hex, none for the last column of a padded row
+        let right_hex_index = match ((i as f32) / (count_x as f32)).floor() as i32 % 2 == 0 {
+            true => {
+                if i as u32 >= count_x {
+                    i - count_x as usize
+                } else {
+                    usize::MAX
+                }
+            }
+            false => {
+                if i as u32 >= count_x - 1 && column + 1 < count_x {
+                    i - (count_x - 1) as usize
+                } else {
+                    usize::MAX
+                }
+            }
+        };
+        // Index of the hex to the top of this hex
+        let top_hex_index = if i as u32 >= 2 * count_x {
+            i - 2 * count_x as usize
+        } else {
+            usize::MAX
+        };
+
+        // Is a hex to the left-top
+        let is_left = if left_hex_index != usize::MAX {
+            hex_points[left_hex_index].is_some()
+        } else {
+            false
+        };
+        // Is a hex to the right-top
+        let is_right = if right_hex_index != usize::MAX {
+            hex_points[right_hex_index].is_some()
+        } else {
+            false
+        };
+        // Is a hex above
+        let is_top = if top_hex_index != usize::MAX {
+            hex_points[top_hex_index].is_some()
+        } else {
+            false
+        };
+
+        // // indexes to topleft-topright-midright-bottomright-bottomleft-midleft particles for this hex
+        // let mut hex_particle_indices: [Option<usize>; 6] = [None; 6];
+
+        // indices to topleft-topright-midright-bottomright-bottomleft-midleft particles for this hex
+        let mut particle_indices: [usize; 6] = [0; 6];
+
+        // Place these if they haven't been placed in prior iteration
+        if !is_left && !is_top {
+            let top_left = vec2(x - hex_radius * cos60, y - hex_radius * sin60);
+            physics_objects
+                .0
+                .push(Particle::new(top_left, Vec2::ZERO, 1.));
+            particle_indices[0] = physics_objects.0.len() - 1;
+        }
+        if !is_right && !is_top {
+            let top_right = vec2(x + hex_radius * cos60, y - hex_radius * sin60);
+            physics_objects
+                .0
+                .push(Particle::new(top_right, Vec2::ZERO, 1.));
+            particle_indices[1] = physics_objects.0.len() - 1;
+        }
+        if !is_left {
+            let mid_left = vec2(x - hex_radius, y);
+            physics_objects
+                .0
+                .push(Particle::new(mid_left, Vec2::ZERO, 1.));
+            particle_indices[5] = physics_objects.0.len() - 1;
+        }
+        if !is_right {
+            let mid_right = vec2(x + hex_radius, y);
+            physics_objects
+                .0
+                .push(Particle::new(mid_right, Vec2::ZERO, 1.));
+            particle_indices[2] = physics_objects.0.len() - 1;
+        }
+
+        // Get the indices of particles from hexagons that placed them first
+        if is_left {
             particle_indices[5] = hex_particles_indices[left_hex_index].unwrap()[3];
             particle_indices[0] = hex_particles_indices[left_hex_index].unwrap()[2];
         }
-        if is_top {
-            if !is_left {
-                // Avoid placing particle previously placed
-                particle_indices[0] = hex_particles_indices[top_hex_index].unwrap()[4];
+        if is_top {
+            if !is_left {
+                // Avoid placing particle previously placed
+                particle_indices[0] = hex_particles_indices[top_hex_index].unwrap()[4];
+            }
+            particle_indices[1] = hex_particles_indices[top_hex_index].unwrap()[3];
+        }
+        if is_right {
+            if !is_top {
+                // Avoid placing particle previously placed
+                particle_indices[1] = hex_particles_indices[right_hex_index].unwrap()[5];
+            }
+            particle_indices[2] = hex_particles_indices[right_hex_index].unwrap()[4];
+        }
+
+        // Unconditionally place because they come in the next iteration (haven't been placed yet no matter what)
+        let bottom_left = vec2(x - hex_radius * cos60, y + hex_radius * sin60);
+        physics_objects
+            .0
+            .push(Particle::new(bottom_left, Vec2::ZERO, 1.));
+        particle_indices[4] = physics_objects.0.len() - 1;
+
+        let bottom_right = vec2(x + hex_radius * cos60, y + hex_radius * sin60);
+        physics_objects
+            .0
+            .push(Particle::new(bottom_right, Vec2::ZERO, 1.));
+        particle_indices[3] = physics_objects.0.len() - 1;
+
+        // Particles shared with earlier hexes keep the material they were placed with
+        for particle in &mut physics_objects.0[first_new_particle..] {
+            particle.material = material;
+        }
+
+        // Update the hex_particles_index with all the particle indices for this hex.
+        hex_particles_indices[i] = Some(particle_indices);
+    });
+
+    // Create the tethers for each hexagon, avoiding placing overlapping tethers
+    let mut created_tethers: HashSet<(usize, usize)> = HashSet::new(); // Lower then higher particle index of created tethers
+    hex_particles_indices
+        .iter()
+        .enumerate()
+        .for_each(|(i, particle_indices_opt)| {
+            // Disregard if no hex here
+            let particle_indices = match particle_indices_opt {
+                Some(indices) => indices,
+                None => return,
+            };
+
+            // Create tethers if not already created. Neighbors list a shared edge in the opposite order.
+            // The last edge closes the hexagon back to the top-left particle.
+            for hex_p_idx in 0..6 {
+                let (a, b) = (
+                    particle_indices[hex_p_idx],
+                    particle_indices[(hex_p_idx + 1) % 6],
+                );
+                if created_tethers.insert((a.min(b), a.max(b))) {
+                    physics_objects.1.push(Tether::new(
+                        a,
+                        b,
+                        stiffness,
+                        damping_constant,
+                        &physics_objects.0,
+                    ));
+                }
+            }
+        });
+}
+
+#[derive(Clone)]
+struct Particle {
+    position: Vec2,
+    rest_position: Vec2, // Where the particle was when its lattice was built or its tethers rebuilt
+    velocity: Vec2,
+    acceleration: Vec2,
+    mass: f32,
+    net_force: Vec2,
+    color: Color,
+    leapfrog_dt: f32, // Timestep of the last leapfrog update, whose second half-kick is still owed
+    collision_group: usize,
+    anchored: bool,  // Anchored particles are held in place, as if infinitely heavy
+    material: usize, // Index into MATERIALS
+    sleeping: bool,  // Settled, so it's left out of integration until something moves it
+    slow_steps: u32, // Steps in a row spent below the sleep speed
+}
+
+impl Particle {
+    fn new(position: Vec2, velocity: Vec2, mass: f32) -> Self {
+        Self {
+            position,
+            rest_position: position,
+            velocity,
+            acceleration: Vec2::ZERO,
+            mass,
+            net_force: Vec2::ZERO,
+            color: Color::from_hex(COLLISION_GROUP_COLORS[0]),
+            leapfrog_dt: 0.,
+            collision_group: 0,
+            anchored: false,
+            material: 0,
+            sleeping: false,
+            slow_steps: 0,
+        }
+    }
+
+    fn wake(&mut self) {
+        self.sleeping = false;
+        self.slow_steps = 0;
+    }
+
+    fn inverse_mass(&self) -> f32 {
+        if self.anchored {
+            0.
+        } else {
+            1. / self.mass
+        }
+    }
+
+    // Velocity at the particle's current position. Leapfrog leaves it half a step behind, with the
+    // rest of the kick owed by the tether forces already in net_force.
+    fn synced_velocity(&self) -> Vec2 {
+        self.velocity + self.net_force * self.inverse_mass() * self.leapfrog_dt / 2.
+    }
+
+    fn set_collision_group(&mut self, group: usize) {
+        self.collision_group = group;
+        self.color = Color::from_hex(COLLISION_GROUP_COLORS[group]);
+    }
+
+    fn apply_force(&mut self, force: Vec2) {
+        self.net_force += force;
+    }
+
+    fn update(&mut self, dt: f32, bounds: Vec2, integrator: Integrator, sleep_speed: Option<f32>) {
+        if self.anchored {
+            self.velocity = Vec2::ZERO;
+            self.net_force = Vec2::ZERO;
+            return;
+        }
+
+        // A sleeping particle stays put, still holding its tethers, until it's pushed hard enough or
+        // something else (a collision or a tool) gives it speed
+        if let Some(sleep_speed) = sleep_speed {
+            if self.sleeping {
+                if self.net_force.length() / self.mass > WAKE_ACCELERATION
+                    || self.velocity.length() > sleep_speed
+                {
+                    self.wake();
+                } else {
+                    self.velocity = Vec2::ZERO;
+                    self.net_force = Vec2::ZERO;
+                    self.leapfrog_dt = 0.;
+                    return;
+                }
             }
-            particle_indices[1] = hex_particles_indices[top_hex_index].unwrap()[3];
         }
-        if is_right {
-            if !is_top {
-                // Avoid placing particle previously placed
-                particle_indices[1] = hex_particles_indices[right_hex_index].unwrap()[5];
+
+        self.acceleration = self.net_force / self.mass;
+        match integrator {
+            Integrator::SemiImplicitEuler => {
+                self.velocity += self.acceleration * dt;
+                self.leapfrog_dt = 0.;
+            }
+            // Velocity is kept half a step ahead of position, so finish the last step's
+            // half-kick and do this step's half-kick with the forces at the current position.
+            Integrator::Leapfrog => {
+                self.velocity += self.acceleration * (self.leapfrog_dt + dt) / 2.;
+                self.leapfrog_dt = dt;
             }
-            particle_indices[2] = hex_particles_indices[right_hex_index].unwrap()[4];
         }
+        let next_position = self.position + self.velocity * dt;
+        self.position = self.sweep_walls(next_position, bounds);
 
-        // Unconditionally place because they come in the next iteration (haven't been placed yet no matter what)
-        let bottom_left = vec2(x - hex_radius * cos60, y + hex_radius * sin60);
+        // Zero out the net force ever frame
+        self.net_force = Vec2::ZERO;
+
+        if let Some(sleep_speed) = sleep_speed {
+            if self.velocity.length() < sleep_speed {
+                self.slow_steps += 1;
+                if self.slow_steps >= SLEEP_STEPS {
+                    self.sleeping = true;
+                    self.velocity = Vec2::ZERO;
+                }
+            } else {
+                self.slow_steps = 0;
+            }
+        }
+    }
+
+    // Swept collision against the walls (0..bounds) so fast particles can't tunnel through them.
+    // Returns where the particle should end up, reflecting its velocity off whichever wall it hit first
+    // and taking the material's friction off the velocity along that wall.
+    fn sweep_walls(&mut self, next_position: Vec2, bounds: Vec2) -> Vec2 {
+        let friction = MATERIALS[self.material].friction;
+        let travel = next_position - self.position;
+
+        // Fraction of the step at which the path crosses each wall (x walls, y walls)
+        let crossing = |old: f32, next: f32, step: f32, max: f32| {
+            if next < 0. && step < 0. {
+                Some((-old / step).max(0.))
+            } else if next > max && step > 0. {
+                Some(((max - old) / step).max(0.))
+            } else {
+                None
+            }
+        };
+        let hit_x = crossing(self.position.x, next_position.x, travel.x, bounds.x);
+        let hit_y = crossing(self.position.y, next_position.y, travel.y, bounds.y);
+
+        let t = match (hit_x, hit_y) {
+            (None, None) => return next_position,
+            (Some(tx), None) => tx,
+            (None, Some(ty)) => ty,
+            (Some(tx), Some(ty)) => tx.min(ty),
+        };
+
+        // Reflect off the wall(s) hit first
+        if hit_x == Some(t) {
+            self.velocity.x = -self.velocity.x;
+            self.velocity.y *= 1. - friction;
+        }
+        if hit_y == Some(t) {
+            self.velocity.y = -self.velocity.y;
+            self.velocity.x *= 1. - friction;
+        }
+
+        // Place at the crossing, making sure it is not left outside on the other axis
+        (self.position + travel * t).clamp(Vec2::ZERO, bounds)
+    }
+
+    // Keep the particle above the floor, bouncing it back up if it was moving down.
+    fn collide_with_floor(&mut self, floor_y: f32) {
+        if self.position.y > floor_y && !self.anchored {
+            let material = &MATERIALS[self.material];
+            self.position.y = floor_y;
+            if self.velocity.y > 0. {
+                self.velocity.y *= -material.restitution;
+            }
+            self.velocity.x *= 1. - material.friction;
+        }
+    }
+
+    // Push the particle out of the polygon through its nearest edge, bouncing off that edge.
+    fn collide_with_polygon(&mut self, polygon: &[Vec2]) {
+        if self.anchored || !point_in_polygon(self.position, polygon) {
+            return;
+        }
+        let Some(exit) = (0..polygon.len())
+            .map(|i| {
+                let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+                closest_point_on_segment(self.position, a, b)
+            })
+            .min_by(|a, b| {
+                a.distance_squared(self.position)
+                    .total_cmp(&b.distance_squared(self.position))
+            })
+        else {
+            return;
+        };
+        let normal = (exit - self.position).normalize_or_zero();
+        self.position = exit;
+
+        let material = &MATERIALS[self.material];
+        let normal_speed = self.velocity.dot(normal);
+        if normal_speed < 0. {
+            let tangential = self.velocity - normal_speed * normal;
+            self.velocity = -material.restitution * normal_speed * normal
+                + (1. - material.friction) * tangential;
+        }
+    }
+
+    fn render(&self, smooth: bool, radius: f32, segments: u8) {
+        if self.anchored {
+            draw_rectangle_lines(
+                self.position.x - 2. * radius,
+                self.position.y - 2. * radius,
+                4. * radius,
+                4. * radius,
+                1.,
+                Color::from_hex(0x3D9BE7),
+            );
+        }
+
+        // Soften the edge with a faint, slightly larger disk underneath
+        if smooth {
+            draw_poly(
+                self.position.x,
+                self.position.y,
+                segments,
+                radius + 0.5,
+                0.,
+                Color::new(self.color.r, self.color.g, self.color.b, 0.35),
+            );
+        }
+        let alpha = if self.sleeping { 0.4 } else { 1. };
+        draw_poly(
+            self.position.x,
+            self.position.y,
+            segments,
+            radius,
+            0.,
+            Color::new(self.color.r, self.color.g, self.color.b, alpha),
+        );
+    }
+}
+
+#[derive(Clone)]
+struct Tether {
+    p1_index: usize,
+    p2_index: usize,
+    k: f32,
+    damping_constant: f32,
+    initial_dist: f32,
+    actuator: Option<Actuator>,
+}
+
+// Makes a tether's rest length oscillate like a muscle.
+#[derive(Clone, Copy)]
+struct Actuator {
+    rest_length: f32, // Rest length the oscillation is centered on
+    amplitude: f32,   // Fraction of rest_length to contract/expand by
+    frequency: f32,   // Cycles per second
+    phase: f32,       // Current point in the cycle, in radians
+}
+
+impl Tether {
+    fn new(
+        p1_index: usize,
+        p2_index: usize,
+        k: f32,
+        damping_constant: f32,
+        particle_arr: &[Particle],
+    ) -> Self {
+        let pos1 = particle_arr[p1_index].position;
+        let pos2 = particle_arr[p2_index].position;
+        Self {
+            p1_index,
+            p2_index,
+            k,
+            damping_constant,
+            initial_dist: (pos2 - pos1).length(),
+            actuator: None,
+        }
+    }
+
+    // Start (or stop, with None) oscillating the rest length, centered on the current rest length.
+    fn set_actuator(&mut self, actuator: Option<Actuator>) {
+        if let Some(old) = self.actuator.take() {
+            self.initial_dist = old.rest_length;
+        }
+        self.actuator = actuator.map(|actuator| Actuator {
+            rest_length: self.initial_dist,
+            ..actuator
+        });
+    }
+
+    // Advance the muscle and return the forces on (p1, p2), leaving the particles untouched.
+    fn update(&mut self, dt: f32, particle_arr: &[Particle], stiffness_scale: f32) -> (Vec2, Vec2) {
+        self.advance_actuator(dt);
+
+        let p1 = &particle_arr[self.p1_index];
+        let p2 = &particle_arr[self.p2_index];
+
+        let dist = (p2.position - p1.position).length();
+        // Coincident particles have no direction to push along, and the correction term divides by
+        // the length. Skip the force this step rather than spreading NaN through the body.
+        if dist < MIN_TETHER_LENGTH {
+            return (Vec2::ZERO, Vec2::ZERO);
+        }
+        let tether_direction = (p2.position - p1.position) / dist;
+
+        let (linear, correction) = self.force_components(dist, stiffness_scale);
+        let f = linear + correction;
+        //let f = -self.k * dx;
+
+        (
+            (f + p1.velocity * self.damping_constant) * -tether_direction,
+            (f + p2.velocity * self.damping_constant) * tether_direction,
+        )
+    }
+
+    // Move the rest length along the muscle's cycle, if it has one.
+    fn advance_actuator(&mut self, dt: f32) {
+        if let Some(actuator) = &mut self.actuator {
+            actuator.phase = (actuator.phase + std::f32::consts::TAU * actuator.frequency * dt)
+                % std::f32::consts::TAU;
+            self.initial_dist =
+                actuator.rest_length * (1. + actuator.amplitude * actuator.phase.sin());
+        }
+    }
+
+    // Move both particles straight to the rest length, split by inverse mass so anchored ones stay put.
+    fn project(&self, particle_arr: &mut [Particle]) {
+        let (p1, p2) = particle_pair_mut(particle_arr, self.p1_index, self.p2_index);
+        let inv_mass1 = p1.inverse_mass();
+        let inv_mass2 = p2.inverse_mass();
+        let offset = p2.position - p1.position;
+        let dist = offset.length();
+        if inv_mass1 + inv_mass2 == 0. || dist == 0. {
+            return;
+        }
+
+        let correction = (dist - self.initial_dist) / (dist * (inv_mass1 + inv_mass2)) * offset;
+        p1.position += correction * inv_mass1;
+        p2.position -= correction * inv_mass2;
+    }
+
+    // Split the force law into the linear spring part and the inverse-square-ish correction.
+    fn force_components(&self, dist: f32, stiffness_scale: f32) -> (f32, f32) {
+        let dx = dist - self.initial_dist;
+        let a = self.initial_dist;
+        (
+            -self.k * stiffness_scale * dx,
+            -10. * (a * dx + a - dx) / (dx + a).powi(2) + 10. / a,
+        )
+    }
+
+    // Relative stretch of the tether (positive when longer than its rest length).
+    fn strain(&self, particle_arr: &[Particle]) -> f32 {
+        let p1 = particle_arr[self.p1_index].position;
+        let p2 = particle_arr[self.p2_index].position;
+        (p2.distance(p1) - self.initial_dist) / self.initial_dist
+    }
+
+    // Energy stored in the tether, found by integrating the force law in update from the rest length.
+    fn potential_energy(&self, particle_arr: &[Particle], stiffness_scale: f32) -> f32 {
+        let p1 = &particle_arr[self.p1_index];
+        let p2 = &particle_arr[self.p2_index];
+
+        let a = self.initial_dist;
+        let u = (p2.position - p1.position).length();
+        let dx = u - a;
+
+        let correction = |u: f32| 10. * ((a - 1.) * u.ln() - (2. * a - a * a) / u);
+        0.5 * self.k * stiffness_scale * dx * dx + correction(u) - correction(a) - 10. * dx / a
+    }
+
+    fn render(
+        &self,
+        particle_arr: &[Particle],
+        smooth: bool,
+        width_scale: f32,
+        batch: &mut LineBatch,
+    ) {
+        let p1 = particle_arr[self.p1_index].position;
+        let p2 = particle_arr[self.p2_index].position;
+        let width = 0.5 * width_scale * self.k / TETHER_STIFFNESS; // Thicker tethers are stiffer
+        let color = match self.actuator {
+            Some(_) => Color::from_hex(0xE76F3D),
+            None => Color::from_hex(0xededed),
+        };
+
+        // Soften the edges with a faint, slightly wider line underneath
+        if smooth {
+            batch.line(
+                p1,
+                p2,
+                width + 1.,
+                Color::new(color.r, color.g, color.b, 0.25),
+            );
+        }
+        batch.line(p1, p2, width, color);
+    }
+}
+
+// A copy of the world stepped with the other integrator, shown beside it.
+struct Comparison {
+    physics_objects: (Vec<Particle>, Vec<Tether>),
+    spatial_grid: SpatialGrid,
+}
+
+// What a physics step reads from GameState, copied out so the step can run on another thread.
+#[derive(Clone)]
+struct PhysicsSettings {
+    integrator: Integrator,
+    physics_mode: PhysicsMode,
+    pbd_iterations: u32,
+    collision_response: CollisionResponse,
+    collision_iterations: u32,
+    gravity: Option<Vec2>, // None while gravity is off
+    floor_y: Option<f32>,  // None while the floor is off
+    obstacles: Arc<Vec<Vec<Vec2>>>,
+    group_collisions: [[bool; COLLISION_GROUP_COLORS.len()]; COLLISION_GROUP_COLORS.len()],
+    stiffness_scale: f32,
+    shatter_threshold: Option<f32>, // None while shattering is off
+    global_damping: f32,
+    wind: Vec2,
+    sleep_speed: Option<f32>, // None while sleeping is off
+}
+
+// Advance the world by dt, then run the hook on it for custom forces, data collection, or deciding to
+// stop. Returns the tethers broken by impacts, as in update_physics, and what the hook returned.
+fn step_with<B>(
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    spatial_grid: &mut SpatialGrid,
+    dt: f32,
+    bounds: Vec2,
+    settings: &PhysicsSettings,
+    mut hook: impl FnMut(&mut (Vec<Particle>, Vec<Tether>)) -> ControlFlow<B>,
+) -> (Vec<usize>, ControlFlow<B>) {
+    let broken = update_physics(physics_objects, spatial_grid, dt, bounds, settings);
+    (broken, hook(physics_objects))
+}
+
+// Advance the world by dt. Returns the indices the tethers broken by impacts had before they were removed,
+// in ascending order.
+fn update_physics(
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    spatial_grid: &mut SpatialGrid,
+    dt: f32,
+    bounds: Vec2,
+    settings: &PhysicsSettings,
+) -> Vec<usize> {
+    let velocities: Vec<Vec2> = match settings.shatter_threshold {
+        Some(_) => physics_objects.0.iter().map(|p| p.velocity).collect(),
+        None => vec![],
+    };
+
+    let mut collisions =
+        spatial_grid.colliding_pairs(&physics_objects.0, PARTICLE_COLLISION_RADIUS);
+    collisions.retain(|&(i, j)| {
+        settings.group_collisions[physics_objects.0[i].collision_group]
+            [physics_objects.0[j].collision_group]
+    });
+
+    if let CollisionResponse::Penalty = settings.collision_response {
+        apply_penalty_collisions(&mut physics_objects.0, &collisions);
+    }
+    if let Some(gravity) = settings.gravity {
+        physics_objects.0.iter_mut().for_each(|particle| {
+            particle.apply_force(particle.mass * gravity);
+        });
+    }
+    // Drag toward the wind's velocity, so bodies are carried along rather than sped up forever
+    if settings.wind != Vec2::ZERO {
+        physics_objects.0.iter_mut().for_each(|particle| {
+            particle.apply_force(particle.mass * WIND_DRAG * (settings.wind - particle.velocity));
+        });
+    }
+    physics_objects.0.iter_mut().for_each(|particle| {
+        particle.update(dt, bounds, settings.integrator, settings.sleep_speed)
+    });
+    if let CollisionResponse::Impulse = settings.collision_response {
+        apply_impulse_collisions(
+            &mut physics_objects.0,
+            &collisions,
+            settings.collision_iterations,
+        );
+    }
+    if let PhysicsMode::Pbd = settings.physics_mode {
+        project_tethers(physics_objects, dt, bounds, settings.pbd_iterations);
+    }
+    if let Some(floor_y) = settings.floor_y {
         physics_objects
             .0
-            .push(Particle::new(bottom_left, Vec2::ZERO, 1.));
-        particle_indices[4] = physics_objects.0.len() - 1;
+            .iter_mut()
+            .for_each(|particle| particle.collide_with_floor(floor_y));
+    }
+    for obstacle in settings.obstacles.iter() {
+        physics_objects
+            .0
+            .iter_mut()
+            .for_each(|particle| particle.collide_with_polygon(obstacle));
+    }
+    let broken = match settings.shatter_threshold {
+        Some(threshold) => shatter_impacts(physics_objects, &velocities, threshold),
+        None => vec![],
+    };
 
-        let bottom_right = vec2(x + hex_radius * cos60, y + hex_radius * sin60);
+    // Read fresh every step, so changing it settles or frees the bodies right away
+    if settings.global_damping > 0. {
+        let factor = (-settings.global_damping * dt).exp();
         physics_objects
             .0
-            .push(Particle::new(bottom_right, Vec2::ZERO, 1.));
-        particle_indices[3] = physics_objects.0.len() - 1;
+            .iter_mut()
+            .for_each(|particle| particle.velocity *= factor);
+    }
 
-        // Update the hex_particles_index with all the particle indices for this hex.
-        hex_particles_indices[i] = Some(particle_indices);
+    if let PhysicsMode::ForceBased = settings.physics_mode {
+        apply_tether_forces(physics_objects, dt, settings.stiffness_scale);
+    }
+
+    // Shared by the next step's collisions and the tools
+    *spatial_grid = SpatialGrid::new(&physics_objects.0, GRID_CELL_SIZE);
+    broken
+}
+
+// Break the tethers between shard-sized pieces of every region whose velocity changed by more than the
+// threshold this step. Tether forces inside a region cancel out in its total momentum, so what's
+// measured is mostly what hit it from outside. Returns the indices of the broken tethers, ascending.
+fn shatter_impacts(
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    previous_velocities: &[Vec2],
+    threshold: f32,
+) -> Vec<usize> {
+    let cell_of = |position: Vec2, size: f32| {
+        (
+            (position.x / size).floor() as i32,
+            (position.y / size).floor() as i32,
+        )
+    };
+
+    // (momentum change, mass) per region
+    let mut regions: HashMap<(i32, i32), (Vec2, f32)> = HashMap::new();
+    for (particle, &before) in physics_objects.0.iter().zip(previous_velocities) {
+        let region = regions
+            .entry(cell_of(particle.position, SHATTER_REGION_SIZE))
+            .or_default();
+        region.0 += particle.mass * (particle.velocity - before);
+        region.1 += particle.mass;
+    }
+    let shattered: HashSet<(i32, i32)> = regions
+        .into_iter()
+        .filter(|(_, (impulse, mass))| *mass > 0. && impulse.length() / *mass > threshold)
+        .map(|(region, _)| region)
+        .collect();
+    if shattered.is_empty() {
+        return vec![];
+    }
+
+    let (particles, tethers) = physics_objects;
+    let mut broken = vec![];
+    let mut i = 0;
+    tethers.retain(|tether| {
+        let p1 = particles[tether.p1_index].position;
+        let p2 = particles[tether.p2_index].position;
+        let hit = shattered.contains(&cell_of(p1, SHATTER_REGION_SIZE))
+            || shattered.contains(&cell_of(p2, SHATTER_REGION_SIZE));
+        let keep = !hit || cell_of(p1, SHARD_SIZE) == cell_of(p2, SHARD_SIZE);
+        if !keep {
+            broken.push(i);
+        }
+        i += 1;
+        keep
     });
+    broken
+}
+
+// A frame's worth of physics steps. The world travels to the physics thread with it and comes back once stepped.
+struct PhysicsJob {
+    physics_objects: (Vec<Particle>, Vec<Tether>),
+    broken_tethers: usize, // Filled in by the physics thread
+    spatial_grid: SpatialGrid,
+    settings: PhysicsSettings,
+    dt: f32,
+    substeps: u32,
+    bounds: Vec2,
+    step_script: StepScript, // Run after every substep, on loan from the game state
+    strain_log: Option<StrainLog>, // Recorded after every substep, on loan from the game state
+    sim_time: f64,           // Advanced by the physics thread
+    steps_taken: u32,        // Fewer than substeps if the step script stopped the batch
+    halt_reason: Option<String>,
+}
+
+// Thread stepping the physics while the main thread draws the frame and waits for the next one.
+// The world is only ever on one side, so tools never edit it while it's being stepped.
+struct PhysicsWorker {
+    jobs: mpsc::Sender<PhysicsJob>,
+    finished: mpsc::Receiver<PhysicsJob>,
+    busy: bool,
+}
+
+impl PhysicsWorker {
+    fn new() -> Self {
+        let (jobs, incoming) = mpsc::channel::<PhysicsJob>();
+        let (outgoing, finished) = mpsc::channel();
+        thread::spawn(move || {
+            // Ends once the main thread drops its sender
+            for mut job in incoming {
+                for _ in 0..job.substeps {
+                    let time = job.sim_time + job.dt as f64;
+                    let (broken, flow) = step_with(
+                        &mut job.physics_objects,
+                        &mut job.spatial_grid,
+                        job.dt,
+                        job.bounds,
+                        &job.settings,
+                        |world| {
+                            job.step_script
+                                .after_step(world, time, job.settings.stiffness_scale)
+                        },
+                    );
+                    job.broken_tethers += broken.len();
+                    job.sim_time = time;
+                    job.steps_taken += 1;
+                    if let Some(strain_log) = &mut job.strain_log {
+                        strain_log.record_step(&job.physics_objects, &broken);
+                    }
+                    if let ControlFlow::Break(reason) = flow {
+                        job.halt_reason = Some(reason);
+                        break;
+                    }
+                }
+                if outgoing.send(job).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            jobs,
+            finished,
+            busy: false,
+        }
+    }
+}
+
+// Hand the world to the physics thread for the step planned this frame, if any.
+fn dispatch_physics(
+    game_state: &mut GameState,
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+) {
+    let Some((dt, substeps)) = game_state.pending_step.take() else {
+        return;
+    };
+    let job = PhysicsJob {
+        physics_objects: std::mem::take(physics_objects),
+        broken_tethers: 0,
+        spatial_grid: std::mem::replace(
+            &mut game_state.spatial_grid,
+            SpatialGrid::new(&[], GRID_CELL_SIZE),
+        ),
+        settings: game_state.physics_settings(),
+        dt,
+        substeps,
+        bounds: game_state.canvas_size(),
+        step_script: std::mem::replace(&mut game_state.step_script, StepScript::Off),
+        strain_log: game_state.strain_log.take(),
+        sim_time: game_state.sim_time,
+        steps_taken: 0,
+        halt_reason: None,
+    };
+    let worker = game_state
+        .physics_worker
+        .get_or_insert_with(PhysicsWorker::new);
+    worker
+        .jobs
+        .send(job)
+        .expect("physics thread stopped unexpectedly");
+    worker.busy = true;
+}
+
+// Wait for the physics thread to finish the dispatched step, if any, and take the world back.
+fn collect_physics(game_state: &mut GameState, physics_objects: &mut (Vec<Particle>, Vec<Tether>)) {
+    let Some(worker) = game_state
+        .physics_worker
+        .as_mut()
+        .filter(|worker| worker.busy)
+    else {
+        return;
+    };
+    let job = worker
+        .finished
+        .recv()
+        .expect("physics thread stopped unexpectedly");
+    worker.busy = false;
+
+    *physics_objects = job.physics_objects;
+    game_state.spatial_grid = job.spatial_grid;
+    game_state.step_script = job.step_script;
+    game_state.strain_log = job.strain_log;
+    game_state.sim_time = job.sim_time;
+    game_state.sim_steps += job.steps_taken as u64;
+    game_state.tethers_broken(job.broken_tethers);
+    if let Some(reason) = job.halt_reason {
+        game_state.halt(reason);
+    }
+}
+
+// Tether forces only read the particles, so they are all computed first and then summed per particle in
+// tether order. The result doesn't depend on the order the tethers are evaluated in.
+fn apply_tether_forces(
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    dt: f32,
+    stiffness_scale: f32,
+) {
+    let forces: Vec<(Vec2, Vec2)> = physics_objects
+        .1
+        .iter_mut()
+        .map(|tether| tether.update(dt, &physics_objects.0, stiffness_scale))
+        .collect();
+
+    let mut net_forces = vec![Vec2::ZERO; physics_objects.0.len()];
+    for (tether, (f1, f2)) in physics_objects.1.iter().zip(forces) {
+        net_forces[tether.p1_index] += f1;
+        net_forces[tether.p2_index] += f2;
+    }
+    for (particle, force) in physics_objects.0.iter_mut().zip(net_forces) {
+        particle.apply_force(force);
+    }
+}
+
+// Satisfy every tether's length by projecting positions, then fold the corrections into the velocities.
+fn project_tethers(
+    physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+    dt: f32,
+    bounds: Vec2,
+    iterations: u32,
+) {
+    physics_objects
+        .1
+        .iter_mut()
+        .for_each(|tether| tether.advance_actuator(dt));
+
+    let integrated: Vec<Vec2> = physics_objects.0.iter().map(|p| p.position).collect();
+    for _ in 0..iterations {
+        physics_objects
+            .1
+            .iter()
+            .for_each(|tether| tether.project(&mut physics_objects.0));
+    }
+    for (particle, integrated) in physics_objects.0.iter_mut().zip(integrated) {
+        particle.position = particle.position.clamp(Vec2::ZERO, bounds);
+        particle.velocity += (particle.position - integrated) / dt;
+    }
+}
+
+// Largest step a particle of this mass stays stable at on tethers whose stiffness adds up to
+// stiffness_sum, 2 / sqrt(k / m).
+fn critical_step_for(stiffness_sum: f32, mass: f32) -> f32 {
+    2. * (mass / stiffness_sum).sqrt()
+}
+
+// Largest step the explicit tether springs stay stable at, critical_step_for the particle whose
+// tethers are stiffest for its mass, taking the stiffness of all its tethers together. Damping and the
+// correction term are left out, so treat it as an estimate. None without free, tethered particles.
+fn critical_timestep(
+    physics_objects: &(Vec<Particle>, Vec<Tether>),
+    stiffness_scale: f32,
+) -> Option<f32> {
+    let mut stiffness = vec![0.; physics_objects.0.len()];
+    for tether in &physics_objects.1 {
+        stiffness[tether.p1_index] += tether.k * stiffness_scale;
+        stiffness[tether.p2_index] += tether.k * stiffness_scale;
+    }
+    physics_objects
+        .0
+        .iter()
+        .zip(&stiffness)
+        .filter(|(particle, &k)| !particle.anchored && k > 0.)
+        .map(|(particle, &k)| critical_step_for(k, particle.mass))
+        .min_by(f32::total_cmp)
+}
+
+// Compare the step being taken, and the longest one that can be, against the critical timestep.
+fn timestep_report(
+    game_state: &GameState,
+    physics_objects: &(Vec<Particle>, Vec<Tether>),
+) -> String {
+    if let PhysicsMode::Pbd = game_state.physics_mode {
+        return "Position-based tethers are projected, the timestep can't make them explode"
+            .to_owned();
+    }
+    let Some(critical) = critical_timestep(physics_objects, game_state.stiffness_scale()) else {
+        return "No tethered particles to check the timestep against".to_owned();
+    };
+
+    let current = get_frame_time().min(game_state.max_dt) / game_state.last_substeps.max(1) as f32;
+    let longest = game_state.max_dt / game_state.max_substeps as f32;
+    if longest <= critical {
+        format!(
+            "Stable: critical dt {:.2} ms, step {:.2} ms, at most {:.2} ms",
+            critical * 1000.,
+            current * 1000.,
+            longest * 1000.
+        )
+    } else {
+        format!(
+            "{}: critical dt {:.2} ms, step {:.2} ms, up to {:.2} ms. Needs {} substeps at the max dt",
+            if current > critical { "UNSTABLE" } else { "At risk" },
+            critical * 1000.,
+            current * 1000.,
+            longest * 1000.,
+            (game_state.max_dt / critical).ceil()
+        )
+    }
+}
 
-    // Create the tethers for each hexagon, avoiding placing overlapping tethers
-    let mut created_tethers: VecDeque<(usize, usize)> = VecDeque::new(); // Indices of particles for created tethers;
-    hex_particles_indices
+// Substeps needed this frame, growing with how far the most strained tether is past the threshold.
+fn adaptive_substeps(
+    physics_objects: &(Vec<Particle>, Vec<Tether>),
+    strain_threshold: f32,
+    max_substeps: u32,
+) -> u32 {
+    let max_strain = physics_objects
+        .1
         .iter()
-        .enumerate()
-        .for_each(|(i, particle_indices_opt)| {
-            // Disregard if no hex here
-            let particle_indices = match particle_indices_opt {
-                Some(indices) => indices,
-                None => return,
-            };
-
-            // Create tethers if not already created
-            for hex_p_idx in 0..5 {
-                if !created_tethers
-                    .contains(&(particle_indices[hex_p_idx], particle_indices[hex_p_idx + 1]))
-                {
-                    created_tethers
-                        .push_back((particle_indices[hex_p_idx], particle_indices[hex_p_idx + 1]));
-                    physics_objects.1.push(Tether::new(
-                        particle_indices[hex_p_idx],
-                        particle_indices[hex_p_idx + 1],
-                        stiffness,
-                        damping_constant,
-                        &physics_objects.0,
-                    ));
-                }
-            }
+        .map(|tether| tether.strain(&physics_objects.0).abs())
+        .fold(0., f32::max);
 
-            // Make sure their aren't more than two rows of stored tether indices for optimization purposes
-            // Only need the two rows above current row to compare placed tethers
-            if created_tethers.len() > 2 * count_x as usize {
-                created_tethers.pop_front();
-            }
-        })
+    if max_strain <= strain_threshold {
+        1
+    } else {
+        ((max_strain / strain_threshold).ceil() as u32).clamp(1, max_substeps)
+    }
 }
 
-struct Particle {
-    position: Vec2,
-    velocity: Vec2,
-    acceleration: Vec2,
-    mass: f32,
-    net_force: Vec2,
-    color: Color,
+// Buckets particle indices by position so nearby particles can be found without scanning them all.
+struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    particle_count: usize, // Number of particles when built, to notice when it's out of date
 }
 
-impl Particle {
-    fn new(position: Vec2, velocity: Vec2, mass: f32) -> Self {
-        Self {
-            position,
-            velocity,
-            acceleration: Vec2::ZERO,
-            mass,
-            net_force: Vec2::ZERO,
-            color: Color::from_hex(0xf2df50),
+impl SpatialGrid {
+    fn new(particles: &[Particle], cell_size: f32) -> Self {
+        let mut grid = Self {
+            cell_size,
+            cells: HashMap::new(),
+            particle_count: particles.len(),
+        };
+        for (i, particle) in particles.iter().enumerate() {
+            let cell = grid.cell_of(particle.position);
+            grid.cells.entry(cell).or_default().push(i);
         }
+        grid
     }
 
-    fn apply_force(&mut self, force: Vec2) {
-        self.net_force += force;
+    fn cell_of(&self, point: Vec2) -> (i32, i32) {
+        (
+            (point.x / self.cell_size).floor() as i32,
+            (point.y / self.cell_size).floor() as i32,
+        )
     }
 
-    fn update(&mut self, dt: f32) {
-        self.acceleration = self.net_force / self.mass;
-        self.velocity += self.acceleration * dt;
-        self.position += self.velocity * dt;
+    // Indices of every particle within radius of the point.
+    fn query_radius(&self, particles: &[Particle], point: Vec2, radius: f32) -> Vec<usize> {
+        let min = self.cell_of(point - Vec2::splat(radius));
+        let max = self.cell_of(point + Vec2::splat(radius));
+        let within = |&i: &usize| {
+            particles
+                .get(i)
+                .is_some_and(|particle| particle.position.distance(point) <= radius)
+        };
 
-        // Zero out the net force ever frame
-        self.net_force = Vec2::ZERO;
+        // A radius reaching past more cells than are occupied (up to the whole plane for an infinite
+        // one) checks the occupied cells instead of walking the range
+        let span =
+            (max.0 as i64 - min.0 as i64 + 1).saturating_mul(max.1 as i64 - min.1 as i64 + 1);
+        if span > self.cells.len() as i64 {
+            return self
+                .cells
+                .values()
+                .flatten()
+                .copied()
+                .filter(within)
+                .collect();
+        }
+
+        let mut found = vec![];
+        for cell_x in min.0..=max.0 {
+            for cell_y in min.1..=max.1 {
+                let Some(cell) = self.cells.get(&(cell_x, cell_y)) else {
+                    continue;
+                };
+                found.extend(cell.iter().copied().filter(within));
+            }
+        }
+        found
     }
 
-    fn render(&self) {
-        draw_circle(self.position.x, self.position.y, 1.5, self.color);
+    // Pairs of particle indices closer than twice the radius.
+    fn colliding_pairs(&self, particles: &[Particle], radius: f32) -> Vec<(usize, usize)> {
+        let mut pairs = vec![];
+        for (i, particle) in particles.iter().enumerate() {
+            for j in self.query_radius(particles, particle.position, 2. * radius) {
+                if j > i && particle.position.distance(particles[j].position) < 2. * radius {
+                    pairs.push((i, j));
+                }
+            }
+        }
+        pairs
     }
 }
 
-struct Tether {
-    p1_index: usize,
-    p2_index: usize,
-    k: f32,
-    damping_constant: f32,
-    initial_dist: f32,
+// Triangles (as point indices) of the Delaunay triangulation of the points, using Bowyer-Watson.
+fn delaunay_triangles(points: &[Vec2]) -> Vec<[usize; 3]> {
+    if points.len() < 3 {
+        return vec![];
+    }
+
+    // Start from one triangle big enough to hold every point, with its corners after the real points
+    let min = points.iter().fold(points[0], |min, p| min.min(*p));
+    let max = points.iter().fold(points[0], |max, p| max.max(*p));
+    let center = (min + max) / 2.;
+    let size = (max - min).max_element().max(1.) * 20.;
+    let mut vertices = points.to_vec();
+    vertices.extend([
+        center + vec2(-size, -size),
+        center + vec2(size, -size),
+        center + vec2(0., size),
+    ]);
+    let n = points.len();
+    let mut triangles = vec![[n, n + 1, n + 2]];
+
+    for (i, point) in points.iter().enumerate() {
+        // Triangles whose circumcircle contains the point no longer belong in the triangulation
+        let (bad, good): (Vec<[usize; 3]>, Vec<[usize; 3]>) = triangles
+            .into_iter()
+            .partition(|triangle| in_circumcircle(&vertices, *triangle, *point));
+        triangles = good;
+
+        // The hole they leave is bounded by the edges only one of them has
+        let mut edge_counts: HashMap<(usize, usize), u32> = HashMap::new();
+        for triangle in &bad {
+            for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+                let edge = (triangle[a].min(triangle[b]), triangle[a].max(triangle[b]));
+                *edge_counts.entry(edge).or_default() += 1;
+            }
+        }
+        triangles.extend(
+            edge_counts
+                .into_iter()
+                .filter(|(_, count)| *count == 1)
+                .map(|((a, b), _)| [a, b, i]),
+        );
+    }
+
+    // Drop everything still attached to the starting triangle
+    triangles.retain(|triangle| triangle.iter().all(|&i| i < n));
+    triangles
 }
 
-impl Tether {
-    fn new(
-        p1_index: usize,
-        p2_index: usize,
-        k: f32,
-        damping_constant: f32,
-        particle_arr: &[Particle],
-    ) -> Self {
-        let pos1 = particle_arr[p1_index].position;
-        let pos2 = particle_arr[p2_index].position;
-        Self {
-            p1_index,
-            p2_index,
-            k,
-            damping_constant,
-            initial_dist: (pos2 - pos1).length(),
+// Whether the point is inside the circle through the triangle's corners (degenerate triangles always contain it).
+// Done in f64 since the starting triangle's corners are far enough out for f32 to lose the small triangles.
+fn in_circumcircle(vertices: &[Vec2], triangle: [usize; 3], point: Vec2) -> bool {
+    let corner = |i: usize| {
+        (
+            vertices[triangle[i]].x as f64,
+            vertices[triangle[i]].y as f64,
+        )
+    };
+    let ((ax, ay), (bx, by), (cx, cy)) = (corner(0), corner(1), corner(2));
+    let d = 2. * (ax * (by - cy) + bx * (cy - ay) + cx * (ay - by));
+    if d.abs() < f64::EPSILON {
+        return true;
+    }
+    let (a2, b2, c2) = (ax * ax + ay * ay, bx * bx + by * by, cx * cx + cy * cy);
+    let center_x = (a2 * (by - cy) + b2 * (cy - ay) + c2 * (ay - by)) / d;
+    let center_y = (a2 * (cx - bx) + b2 * (ax - cx) + c2 * (bx - ax)) / d;
+    let distance_squared = |x: f64, y: f64| (x - center_x).powi(2) + (y - center_y).powi(2);
+    distance_squared(point.x as f64, point.y as f64) < distance_squared(ax, ay)
+}
+
+// Get mutable references to two different particles at once.
+fn particle_pair_mut(
+    particles: &mut [Particle],
+    i: usize,
+    j: usize,
+) -> (&mut Particle, &mut Particle) {
+    match i.cmp(&j) {
+        Ordering::Less => {
+            let (start, end) = particles.split_at_mut(j);
+            (&mut start[i], &mut end[0])
         }
+        Ordering::Greater => {
+            let (start, end) = particles.split_at_mut(i);
+            (&mut end[0], &mut start[j])
+        }
+        Ordering::Equal => panic!("Both particles are the same in a pair."),
     }
+}
 
-    fn update(&mut self, dt: f32, particle_arr: &mut [Particle]) {
-        let (p1, p2) = match self.p1_index.cmp(&self.p2_index) {
-            Ordering::Less => {
-                let (start, end) = particle_arr.split_at_mut(self.p2_index);
-                (&mut start[self.p1_index], &mut end[0])
-            }
-            Ordering::Greater => {
-                let (start, end) = particle_arr.split_at_mut(self.p1_index);
-                (&mut end[0], &mut start[self.p2_index])
-            }
-            Ordering::Equal => panic!("Both particles are the same in a tether."),
-        };
+// Push overlapping particles apart with a spring-like force proportional to their overlap.
+fn apply_penalty_collisions(particles: &mut [Particle], collisions: &[(usize, usize)]) {
+    for &(i, j) in collisions {
+        let (p1, p2) = particle_pair_mut(particles, i, j);
+        let offset = p2.position - p1.position;
+        let overlap = 2. * PARTICLE_COLLISION_RADIUS - offset.length();
+        let normal = offset.normalize_or_zero();
 
-        let dist = (p2.position - p1.position).length();
-        let tether_direction = (p2.position - p1.position).normalize();
+        p1.apply_force(-COLLISION_STIFFNESS * overlap * normal);
+        p2.apply_force(COLLISION_STIFFNESS * overlap * normal);
+        let (_, friction) = combined_material(p1, p2);
+        apply_contact_friction(p1, p2, normal, friction);
+    }
+}
 
-        let dx = dist - self.initial_dist;
-        let a = self.initial_dist;
-        let f = -self.k * dx - 10. * (a * dx + a - dx) / (dx + a).powi(2) + 10. / a;
-        //let f = -self.k * dx;
+// Restitution and friction for a contact between two particles, averaged over their materials.
+fn combined_material(p1: &Particle, p2: &Particle) -> (f32, f32) {
+    let (m1, m2) = (&MATERIALS[p1.material], &MATERIALS[p2.material]);
+    (
+        (m1.restitution + m2.restitution) / 2.,
+        (m1.friction + m2.friction) / 2.,
+    )
+}
 
-        p1.apply_force((f + p1.velocity * self.damping_constant) * -tether_direction);
-        p2.apply_force((f + p2.velocity * self.damping_constant) * tether_direction);
+// Take a fraction of the relative sliding velocity off two touching particles, split by inverse mass.
+fn apply_contact_friction(p1: &mut Particle, p2: &mut Particle, normal: Vec2, friction: f32) {
+    let inv_mass1 = p1.inverse_mass();
+    let inv_mass2 = p2.inverse_mass();
+    if inv_mass1 + inv_mass2 == 0. {
+        return;
     }
+    let relative_velocity = p2.velocity - p1.velocity;
+    let sliding = relative_velocity - relative_velocity.dot(normal) * normal;
+    let change = friction * sliding / (inv_mass1 + inv_mass2);
+    p1.velocity += change * inv_mass1;
+    p2.velocity -= change * inv_mass2;
+}
 
-    fn render(&self, particle_arr: &[Particle]) {
-        let p1 = &particle_arr[self.p1_index];
-        let p2 = &particle_arr[self.p2_index];
-        draw_line(
-            p1.position.x,
-            p1.position.y,
-            p2.position.x,
-            p2.position.y,
-            0.5,
-            Color::from_hex(0xededed),
-        );
+// Remove the approaching velocity of overlapping particles and separate them directly. Each pass
+// works from where the last one left the particles, so pushes travel down through a stack. Friction
+// is only applied on the first pass so it doesn't compound.
+fn apply_impulse_collisions(
+    particles: &mut [Particle],
+    collisions: &[(usize, usize)],
+    iterations: u32,
+) {
+    for pass in 0..iterations {
+        for &(i, j) in collisions {
+            resolve_impulse_collision(particles, i, j, pass == 0);
+        }
     }
 }
 
-fn update_physics(physics_objects: &mut (Vec<Particle>, Vec<Tether>), dt: f32) {
-    physics_objects
-        .0
-        .iter_mut()
-        .for_each(|particle| particle.update(dt));
-    physics_objects.1.iter_mut().for_each(|tether| {
-        tether.update(dt, &mut physics_objects.0);
-    });
+fn resolve_impulse_collision(particles: &mut [Particle], i: usize, j: usize, with_friction: bool) {
+    let (p1, p2) = particle_pair_mut(particles, i, j);
+    let offset = p2.position - p1.position;
+    let overlap = 2. * PARTICLE_COLLISION_RADIUS - offset.length();
+    let normal = offset.normalize_or_zero();
+    if overlap <= 0. || normal == Vec2::ZERO {
+        return;
+    }
+    let inv_mass1 = p1.inverse_mass();
+    let inv_mass2 = p2.inverse_mass();
+    if inv_mass1 + inv_mass2 == 0. {
+        return; // Both are anchored
+    }
+
+    // Only resolve pairs that are still approaching each other
+    let (restitution, friction) = combined_material(p1, p2);
+    let approach_speed = (p2.velocity - p1.velocity).dot(normal);
+    if approach_speed < 0. {
+        let impulse = -(1. + restitution) * approach_speed / (inv_mass1 + inv_mass2);
+        p1.velocity -= impulse * inv_mass1 * normal;
+        p2.velocity += impulse * inv_mass2 * normal;
+    }
+    if with_friction {
+        apply_contact_friction(p1, p2, normal, friction);
+    }
+
+    // Split the separation by inverse mass so lighter particles move further
+    let correction = overlap / (inv_mass1 + inv_mass2) * normal;
+    p1.position -= correction * inv_mass1;
+    p2.position += correction * inv_mass2;
+}
+
+// Total kinetic energy of the particles.
+fn kinetic_energy(particles: &[Particle]) -> f32 {
+    particles
+        .iter()
+        .map(|particle| 0.5 * particle.mass * particle.synced_velocity().length_squared())
+        .sum()
+}
+
+// Kinetic energy of the particles plus the energy stored in the tethers.
+fn total_energy(physics_objects: &(Vec<Particle>, Vec<Tether>), stiffness_scale: f32) -> f32 {
+    let kinetic = kinetic_energy(&physics_objects.0);
+    let potential: f32 = physics_objects
+        .1
+        .iter()
+        .map(|tether| tether.potential_energy(&physics_objects.0, stiffness_scale))
+        .sum();
+    kinetic + potential
 }
 
 #[macroquad::main(config_window)]
 async fn main() {
     let mut current_mode = Mode::Create;
     let mut game_state = GameState::new();
-    let mut create_canvas = RgbaImage::new(screen_width() as u32, screen_height() as u32); // Image for drawing squishies
+    game_state.load_config();
+    match load_obstacles() {
+        Ok(obstacles) => game_state.obstacles = Arc::new(obstacles),
+        Err(error) => eprintln!("Can't load obstacles: {}", error),
+    }
+    match load_timeline() {
+        Ok(timeline) => game_state.set_timeline(timeline),
+        Err(error) => eprintln!("Can't load timeline: {}", error),
+    }
 
     // Store all physics objects
     let mut physics_objects: (Vec<Particle>, Vec<Tether>) = (vec![], vec![]);
 
+    game_state.resize_canvas();
     let t = Texture2D::from_rgba8(
-        create_canvas.width() as u16,
-        create_canvas.height() as u16,
-        &create_canvas,
+        game_state.canvas_size().x as u16,
+        game_state.canvas_size().y as u16,
+        &game_state.flatten_layers(),
     );
 
     loop {
-        clear_background(Color::from_hex(0x0E131F));
+        // Everything is drawn in canvas coordinates, letterboxed into the window
+        clear_background(BLACK);
+        set_camera(&game_state.canvas_camera());
+        draw_rectangle(
+            0.,
+            0.,
+            game_state.canvas_size().x,
+            game_state.canvas_size().y,
+            Color::from_hex(game_state.background_color),
+        );
+
+        // Everything below works on the stepped world
+        collect_physics(&mut game_state, &mut physics_objects);
 
-        if is_key_pressed(KeyCode::Space) {
+        // macroquad reports no focus changes, so a button let go while the window was in the background
+        // never reaches the tools, and macroquad keeps reporting it held. A press can't come in the
+        // middle of a drag, so one arriving while a drag is still in progress means its release was
+        // missed. Every press starts a fresh stroke or grab rather than joining up with the stale one.
+        // A drag is also dropped once no button is held or just released.
+        let buttons = [MouseButton::Left, MouseButton::Right, MouseButton::Middle];
+        if buttons.into_iter().any(is_mouse_button_pressed)
+            || !buttons
+                .into_iter()
+                .any(|button| is_mouse_button_down(button) || is_mouse_button_released(button))
+        {
+            game_state.reset_pointer_state();
+        }
+        let render_start = get_time();
+
+        // Typing a radius takes over the keyboard (and pauses the mode) until it's applied or cancelled
+        let typing = game_state.radius_entry.is_some();
+        if typing {
+            handle_radius_entry(&mut game_state, current_mode);
+        }
+
+        if !typing && is_key_pressed(KeyCode::Space) {
             current_mode = switch_modes(current_mode);
+            game_state.comparison = None;
+        }
+
+        // Quit, keeping the current settings for next time
+        if !typing && is_key_pressed(KeyCode::Escape) {
+            if let Err(error) = game_state.save_config() {
+                eprintln!("Can't save config: {}", error);
+            }
+            break;
+        }
+
+        if !typing {
+            handle_shared_logic(&mut game_state, &mut physics_objects);
+            handle_tool_presets(&mut game_state, current_mode);
         }
 
         // Handle all logic pertaining to each mode
         match current_mode {
             Mode::Create => {
-                handle_create_logic(&mut game_state, &mut create_canvas, &mut physics_objects);
-                // Update and draw the draw stuff if on create mode.
-                t.update(&Image {
-                    bytes: create_canvas.to_vec(),
-                    width: create_canvas.width() as u16,
-                    height: create_canvas.height() as u16,
-                });
+                if !typing {
+                    handle_create_logic(&mut game_state, &mut physics_objects);
+                }
+                // Update and draw the draw stuff if on create mode, only re-uploading after changes.
+                if game_state.canvas_dirty {
+                    game_state.canvas_empty = !game_state.drawing_exists();
+                    let create_canvas = game_state.flatten_layers(); // Image for drawing squishies
+                    t.update(&Image {
+                        width: create_canvas.width() as u16,
+                        height: create_canvas.height() as u16,
+                        bytes: create_canvas.into_raw(),
+                    });
+                    game_state.canvas_dirty = false;
+                }
                 draw_texture(t, 0., 0., Color::from_rgba(255, 255, 255, 255));
             }
-            Mode::Sim => handle_sim_logic(&mut game_state, &mut physics_objects),
+            Mode::Sim if !typing => handle_sim_logic(&mut game_state, &mut physics_objects),
+            Mode::Sim => (),
         };
 
         // Render the UI on top of the drawing.
         render(current_mode, &mut game_state, &physics_objects);
 
+        // Capture the finished frame, then mark that a recording is running
+        let style = game_state.render_style();
+        if let Some(recording) = &mut game_state.recording {
+            if let Err(error) = recording.capture_frame(&physics_objects, style) {
+                game_state.recording = None;
+                game_state.show_notice(format!("Recording stopped: {}", error));
+            } else {
+                set_camera(&game_state.canvas_camera());
+                draw_circle(
+                    game_state.canvas_size().x - 14.,
+                    14.,
+                    6.,
+                    Color::from_hex(0xE73D71),
+                );
+            }
+        }
+
+        // Judge the render quality by the frame's own work, leaving out physics and the wait for vsync
+        game_state.update_adaptive_quality((get_time() - render_start) as f32);
+
+        // Step in the background while the frame is presented
+        dispatch_physics(&mut game_state, &mut physics_objects);
+
         next_frame().await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A blank canvas with the default material drawn on the given pixels.
+    fn canvas_with(width: u32, height: u32, drawn: &[(u32, u32)]) -> RgbaImage {
+        let mut canvas = RgbaImage::new(width, height);
+        for &(x, y) in drawn {
+            canvas.put_pixel(x, y, Rgba(DRAW_COLOR));
+        }
+        canvas
+    }
+
+    fn lattice_settings(lattice_type: LatticeType) -> LatticeSettings {
+        LatticeSettings {
+            lattice_type,
+            brace_squares: false,
+            spacing: 10.,
+            stiffness: TETHER_STIFFNESS,
+            damping: 0.,
+            total_mass: None,
+            remove_orphans: false,
+            weld: false,
+        }
+    }
+
+    #[test]
+    fn single_hexagon() {
+        // Hexagon centers sit every 30 px across and 8.66 px down at this spacing, (30, 17) is row 2
+        let canvas = canvas_with(100, 100, &[(30, 17)]);
+        let physics_objects = build_lattice(&canvas, &lattice_settings(LatticeType::Hexagonal));
+        assert_eq!(physics_objects.0.len(), 6);
+        assert_eq!(physics_objects.1.len(), 6);
+        assert_eq!(invariant_violation(&physics_objects), None);
+    }
+
+    #[test]
+    fn hexagon_block() {
+        // Rows 2 and 3, columns 1 and 2: a strip of four hexagons, each sharing an edge with the next
+        let canvas = canvas_with(100, 100, &[(30, 17), (60, 17), (45, 25), (75, 25)]);
+        let physics_objects = build_lattice(&canvas, &lattice_settings(LatticeType::Hexagonal));
+        assert_eq!(physics_objects.0.len(), 4 * 6 - 3 * 2);
+        assert_eq!(physics_objects.1.len(), 4 * 6 - 3);
+        assert_eq!(invariant_violation(&physics_objects), None);
+    }
+
+    #[test]
+    fn square_block() {
+        let canvas = canvas_with(100, 100, &[(15, 15), (25, 15), (15, 25), (25, 25)]);
+        let mut settings = lattice_settings(LatticeType::Square);
+        let physics_objects = build_lattice(&canvas, &settings);
+        assert_eq!(physics_objects.0.len(), 9);
+        assert_eq!(physics_objects.1.len(), 12);
+
+        settings.brace_squares = true;
+        let physics_objects = build_lattice(&canvas, &settings);
+        assert_eq!(physics_objects.0.len(), 9);
+        assert_eq!(physics_objects.1.len(), 12 + 4 * 2);
+    }
+
+    // A full window at a fine spacing, about 11000 hexagons.
+    fn large_build_settings() -> LatticeSettings {
+        let mut settings = lattice_settings(LatticeType::Hexagonal);
+        settings.spacing = 4.;
+        settings
+    }
+
+    #[test]
+    fn large_build_counts() {
+        let canvas = ImageBuffer::from_pixel(800, 600, Rgba(DRAW_COLOR));
+        let settings = large_build_settings();
+        let physics_objects = build_lattice(&canvas, &settings);
+
+        assert_eq!(physics_objects.0.len(), 23140);
+        assert_eq!(physics_objects.1.len(), 34491);
+        // Every tether is a hexagon edge, none reach across to a hexagon that isn't a neighbor
+        assert!(physics_objects
+            .1
+            .iter()
+            .all(|tether| (tether.initial_dist - settings.spacing).abs() < 0.01));
+    }
+
+    // Wall-clock time depends on the machine, so this is only run on request with `cargo test -- --ignored`.
+    // Debug builds take under a tenth of the budget.
+    #[test]
+    #[ignore]
+    fn large_build_time() {
+        let canvas = ImageBuffer::from_pixel(800, 600, Rgba(DRAW_COLOR));
+        let start = std::time::Instant::now();
+        build_lattice(&canvas, &large_build_settings());
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed < std::time::Duration::from_secs(2),
+            "building took {:?}",
+            elapsed
+        );
+    }
+
+    fn physics_settings(integrator: Integrator) -> PhysicsSettings {
+        PhysicsSettings {
+            integrator,
+            physics_mode: PhysicsMode::ForceBased,
+            pbd_iterations: 1,
+            collision_response: CollisionResponse::Penalty,
+            collision_iterations: 1,
+            gravity: None,
+            floor_y: None,
+            obstacles: Arc::new(vec![]),
+            group_collisions: [[true; COLLISION_GROUP_COLORS.len()]; COLLISION_GROUP_COLORS.len()],
+            stiffness_scale: 1.,
+            shatter_threshold: None,
+            global_damping: 0.,
+            wind: Vec2::ZERO,
+            sleep_speed: None,
+        }
+    }
+
+    // Worst relative change in total energy of a plucked strip of hexagons over 1000 steps.
+    fn energy_drift(integrator: Integrator) -> f32 {
+        let canvas = canvas_with(100, 100, &[(30, 17), (60, 17), (45, 25), (75, 25)]);
+        let mut physics_objects = build_lattice(&canvas, &lattice_settings(LatticeType::Hexagonal));
+        // Well away from the walls, and with collisions off, so nothing but the integrator can take
+        // energy out or put it in
+        for particle in &mut physics_objects.0 {
+            particle.position += vec2(450., 450.);
+        }
+        physics_objects.0[7].position += vec2(2., 1.);
+        let mut settings = physics_settings(integrator);
+        settings.group_collisions =
+            [[false; COLLISION_GROUP_COLORS.len()]; COLLISION_GROUP_COLORS.len()];
+
+        let mut spatial_grid = SpatialGrid::new(&physics_objects.0, GRID_CELL_SIZE);
+        let start = total_energy(&physics_objects, 1.);
+        (0..1000)
+            .map(|_| {
+                update_physics(
+                    &mut physics_objects,
+                    &mut spatial_grid,
+                    1. / 480.,
+                    vec2(1000., 1000.),
+                    &settings,
+                );
+                ((total_energy(&physics_objects, 1.) - start) / start).abs()
+            })
+            .fold(0., f32::max)
+    }
+
+    #[test]
+    fn leapfrog_energy_drift() {
+        // About 2.6% for leapfrog against 17% for semi-implicit Euler
+        let leapfrog = energy_drift(Integrator::Leapfrog);
+        let euler = energy_drift(Integrator::SemiImplicitEuler);
+        assert!(leapfrog < 0.05, "leapfrog drifted by {}", leapfrog);
+        assert!(
+            euler > 3. * leapfrog,
+            "semi-implicit Euler drifted by {}, leapfrog by {}",
+            euler,
+            leapfrog
+        );
+    }
+
+    #[test]
+    fn fast_particle_stays_inside_walls() {
+        let bounds = vec2(100., 100.);
+        let dt = 1. / 60.;
+        let size = 2. * PARTICLE_COLLISION_RADIUS;
+        for sizes_per_step in [0.5, 1., 10., 100., 1000.] {
+            for direction in [Vec2::X, -Vec2::X, Vec2::Y, -Vec2::Y] {
+                let velocity = direction * sizes_per_step * size / dt;
+                // Close enough to the wall that even the slowest shot reaches it this step
+                let mut particle = Particle::new(vec2(50., 50.) + direction * 48., velocity, 1.);
+                particle.update(dt, bounds, Integrator::SemiImplicitEuler, None);
+
+                let position = particle.position;
+                assert!(
+                    (0. ..=bounds.x).contains(&position.x)
+                        && (0. ..=bounds.y).contains(&position.y),
+                    "{} sizes per step toward {} ended at {}",
+                    sizes_per_step,
+                    direction,
+                    position
+                );
+                assert_eq!(particle.velocity, -velocity);
+            }
+        }
+    }
+
+    #[test]
+    fn strokes_off_the_canvas() {
+        let mut canvas = RgbaImage::new(800, 600);
+        let color = Rgba(DRAW_COLOR);
+        let strokes = [
+            ((-50., -50.), (900., 700.)),
+            ((799., 599.), (800., 600.)),
+            ((-5., 300.), (-5., 300.)),
+            ((400., -1000.), (400., 1000.)),
+            ((f32::MAX, 0.), (0., f32::MIN)),
+        ];
+        for (pos1, pos2) in strokes {
+            draw_rounded_line(&mut canvas, pos1, pos2, 20., color);
+        }
+        // Clamped onto the corners rather than dropped
+        assert!(is_drawn(canvas.get_pixel(0, 0)));
+        assert!(is_drawn(canvas.get_pixel(799, 599)));
+    }
+
+    #[test]
+    fn fills_seeded_off_the_canvas() {
+        for seed in [(-5., -5.), (800., 600.), (1e9, -1e9)] {
+            let mut canvas = RgbaImage::new(800, 600);
+            let (x, y) = clamp_to_canvas(&canvas, seed);
+            flood_fill(
+                &mut canvas,
+                (x as u32, y as u32),
+                Rgba(DRAW_COLOR),
+                FillPattern::Solid,
+            );
+            assert!(canvas.pixels().all(is_drawn));
+        }
+        // Seeds already in pixels are clamped by the fill itself too
+        let mut canvas = RgbaImage::new(800, 600);
+        flood_fill(
+            &mut canvas,
+            (800, 600),
+            Rgba(DRAW_COLOR),
+            FillPattern::Hatch,
+        );
+        flood_fill(
+            &mut canvas,
+            (u32::MAX, u32::MAX),
+            Rgba(DRAW_COLOR),
+            FillPattern::Solid,
+        );
+    }
+
+    #[test]
+    fn orphans_removed() {
+        let canvas = canvas_with(100, 100, &[(30, 17), (60, 17), (45, 25), (75, 25)]);
+        let mut physics_objects = build_lattice(&canvas, &lattice_settings(LatticeType::Hexagonal));
+        // An untethered particle in the middle of the list, so every later index has to shift down
+        let orphan = vec2(-100., -100.);
+        physics_objects
+            .0
+            .insert(5, Particle::new(orphan, Vec2::ZERO, 1.));
+        for tether in &mut physics_objects.1 {
+            tether.p1_index += (tether.p1_index >= 5) as usize;
+            tether.p2_index += (tether.p2_index >= 5) as usize;
+        }
+        let ends = |physics_objects: &(Vec<Particle>, Vec<Tether>)| -> Vec<(Vec2, Vec2)> {
+            physics_objects
+                .1
+                .iter()
+                .map(|tether| {
+                    (
+                        physics_objects.0[tether.p1_index].position,
+                        physics_objects.0[tether.p2_index].position,
+                    )
+                })
+                .collect()
+        };
+        let before = ends(&physics_objects);
+
+        assert_eq!(remove_orphan_particles(&mut physics_objects), 1);
+        assert_eq!(physics_objects.0.len(), 18);
+        assert!(physics_objects
+            .0
+            .iter()
+            .all(|particle| particle.position != orphan));
+        assert!(physics_objects.1.iter().all(|tether| {
+            tether.p1_index < physics_objects.0.len() && tether.p2_index < physics_objects.0.len()
+        }));
+        // Every tether still joins the same two particles
+        assert_eq!(ends(&physics_objects), before);
+        assert_eq!(remove_orphan_particles(&mut physics_objects), 0);
+    }
+
+    #[test]
+    fn decimation_keeps_tethers_valid() {
+        // Two bodies with a gap between them, filled in whole
+        let mut canvas = RgbaImage::new(300, 200);
+        for (x, y, pixel) in canvas.enumerate_pixels_mut() {
+            if ((20..120).contains(&x) && (20..180).contains(&y)) || (180..280).contains(&x) {
+                *pixel = Rgba(DRAW_COLOR);
+            }
+        }
+        let mut settings = lattice_settings(LatticeType::Hexagonal);
+        settings.spacing = 4.;
+        let mut physics_objects = build_lattice(&canvas, &settings);
+
+        for _ in 0..3 {
+            let before = physics_objects.0.len();
+            decimate_lattice(&mut physics_objects, TETHER_STIFFNESS, 0.);
+            assert!(physics_objects.0.len() < before);
+            assert!(!physics_objects.1.is_empty());
+            assert_eq!(invariant_violation(&physics_objects), None);
+            // Survivors are only tethered within their own body
+            assert!(physics_objects.1.iter().all(|tether| {
+                let x1 = physics_objects.0[tether.p1_index].position.x;
+                let x2 = physics_objects.0[tether.p2_index].position.x;
+                (x1 < 150.) == (x2 < 150.)
+            }));
+        }
+    }
+
+    #[test]
+    fn weld_merges_coincident_particles() {
+        let particles = vec![
+            Particle::new(vec2(100., 100.), vec2(10., 0.), 1.),
+            Particle::new(vec2(110., 100.), vec2(0., 5.), 2.),
+            Particle::new(vec2(100., 100.), vec2(-2., 4.), 3.), // On top of the first
+            Particle::new(vec2(100., 120.), vec2(1., 1.), 1.),
+        ];
+        let tethers = [(0, 1), (2, 1), (0, 2), (2, 3)]
+            .map(|(p1, p2)| Tether::new(p1, p2, TETHER_STIFFNESS, 0., &particles))
+            .to_vec();
+        let mut physics_objects = (particles, tethers);
+        let mass = |particles: &[Particle]| particles.iter().map(|p| p.mass).sum::<f32>();
+        let momentum = |particles: &[Particle]| {
+            particles
+                .iter()
+                .fold(Vec2::ZERO, |total, p| total + p.mass * p.velocity)
+        };
+        let (mass_before, momentum_before) =
+            (mass(&physics_objects.0), momentum(&physics_objects.0));
+
+        assert_eq!(weld_duplicate_particles(&mut physics_objects), 1);
+        assert_eq!(physics_objects.0.len(), 3);
+        assert_eq!(physics_objects.0[0].mass, 4.);
+        // The self-tether is dropped and the second tether to the right particle is a duplicate
+        let pairs: Vec<(usize, usize)> = physics_objects
+            .1
+            .iter()
+            .map(|tether| (tether.p1_index, tether.p2_index))
+            .collect();
+        assert_eq!(pairs, [(0, 1), (0, 2)]);
+        assert_eq!(invariant_violation(&physics_objects), None);
+        assert_eq!(mass(&physics_objects.0), mass_before);
+        assert!(momentum(&physics_objects.0).abs_diff_eq(momentum_before, 1e-4));
+    }
+
+    #[test]
+    fn strain_log_follows_removed_tethers() {
+        let mut physics_objects = (
+            (0..6)
+                .map(|i| Particle::new(vec2(10. * i as f32, 0.), Vec2::ZERO, 1.))
+                .collect::<Vec<_>>(),
+            vec![],
+        );
+        for i in 0..5 {
+            let tether = Tether::new(i, i + 1, TETHER_STIFFNESS, 0.5, &physics_objects.0);
+            physics_objects.1.push(tether);
+        }
+        // Stretch every tether by a different amount
+        for (i, particle) in physics_objects.0.iter_mut().enumerate() {
+            particle.position.x += (i * i) as f32;
+        }
+        let strains: Vec<f32> = physics_objects
+            .1
+            .iter()
+            .map(|tether| tether.strain(&physics_objects.0))
+            .collect();
+
+        let mut strain_log = StrainLog::new(vec![1, 3, 4]);
+        strain_log.record_step(&physics_objects, &[]);
+        physics_objects.1.remove(3);
+        physics_objects.1.remove(0);
+        strain_log.record_step(&physics_objects, &[0, 3]);
+        // Deleted outside a step, like the edit tool does
+        physics_objects.1.remove(1);
+        strain_log.tethers_removed(&[1]);
+        strain_log.record_step(&physics_objects, &[]);
+
+        assert_eq!(
+            strain_log.samples[0],
+            vec![strains[1], strains[3], strains[4]]
+        );
+        for step in &strain_log.samples[1..] {
+            assert_eq!(step[0], strains[1]);
+            assert!(step[1].is_nan());
+            assert_eq!(step[2], strains[4]);
+        }
+    }
+
+    #[test]
+    fn coincident_tether_forces_finite() {
+        let mut physics_objects = (
+            vec![
+                Particle::new(vec2(50., 50.), vec2(3., -1.), 1.),
+                Particle::new(vec2(60., 50.), Vec2::ZERO, 1.),
+            ],
+            vec![],
+        );
+        let tether = Tether::new(0, 1, TETHER_STIFFNESS, 0.5, &physics_objects.0);
+        physics_objects.1.push(tether);
+        // Pushed together after building, so the tether still wants its rest length back
+        physics_objects.0[1].position = physics_objects.0[0].position;
+
+        let (f1, f2) = physics_objects.1[0].update(1. / 240., &physics_objects.0, 1.);
+        assert!(f1.is_finite() && f2.is_finite());
+
+        apply_tether_forces(&mut physics_objects, 1. / 240., 1.);
+        for particle in &mut physics_objects.0 {
+            particle.update(
+                1. / 240.,
+                vec2(100., 100.),
+                Integrator::SemiImplicitEuler,
+                None,
+            );
+        }
+        assert!(physics_objects
+            .0
+            .iter()
+            .all(|particle| particle.velocity.is_finite() && particle.position.is_finite()));
+    }
+
+    #[test]
+    fn nearest_particle_ties_and_empty_world() {
+        let point = vec2(100., 100.);
+        // 1, 2 and 3 are all 5 px away, 2 sits in a grid cell before 1's
+        let particles: Vec<Particle> = [
+            vec2(400., 400.),
+            vec2(105., 100.),
+            vec2(95., 100.),
+            vec2(100., 95.),
+        ]
+        .into_iter()
+        .map(|position| Particle::new(position, Vec2::ZERO, 1.))
+        .collect();
+        let grid = SpatialGrid::new(&particles, GRID_CELL_SIZE);
+        let stale_grid = SpatialGrid::new(&particles[..2], GRID_CELL_SIZE);
+        for grid in [Some(&grid), Some(&stale_grid), None] {
+            assert_eq!(nearest_particle(&particles, grid, point, 10.), Some(1));
+            assert_eq!(nearest_particle(&particles, grid, point, 4.), None);
+            assert_eq!(
+                nearest_particle(&particles, grid, vec2(399., 400.), 10.),
+                Some(0)
+            );
+        }
+
+        let empty_grid = SpatialGrid::new(&[], GRID_CELL_SIZE);
+        for grid in [Some(&empty_grid), Some(&grid), None] {
+            assert_eq!(nearest_particle(&[], grid, point, f32::INFINITY), None);
+        }
+    }
+
+    // Step a second of 60 fps frames, each split into the given number of substeps. Returns how long it
+    // took and how many steps were taken.
+    fn run_frames(
+        physics_objects: &mut (Vec<Particle>, Vec<Tether>),
+        substeps: impl Fn(&(Vec<Particle>, Vec<Tether>)) -> u32,
+    ) -> (std::time::Duration, u32) {
+        let settings = physics_settings(Integrator::SemiImplicitEuler);
+        let mut spatial_grid = SpatialGrid::new(&physics_objects.0, GRID_CELL_SIZE);
+        let start = std::time::Instant::now();
+        let mut steps = 0;
+        for _ in 0..60 {
+            let substeps = substeps(physics_objects);
+            for _ in 0..substeps {
+                update_physics(
+                    physics_objects,
+                    &mut spatial_grid,
+                    1. / 60. / substeps as f32,
+                    vec2(800., 600.),
+                    &settings,
+                );
+            }
+            steps += substeps;
+        }
+        (start.elapsed(), steps)
+    }
+
+    #[test]
+    fn adaptive_substeps_cheaper_when_settled() {
+        // A debug build takes about 100 ms adaptive against 800 ms fixed
+        let mut canvas = RgbaImage::new(800, 600);
+        for (x, y, pixel) in canvas.enumerate_pixels_mut() {
+            if (250..550).contains(&x) && (200..400).contains(&y) {
+                *pixel = Rgba(DRAW_COLOR);
+            }
+        }
+        let built = build_lattice(&canvas, &lattice_settings(LatticeType::Hexagonal));
+
+        // At rest in its built shape, so no tether is strained
+        let (adaptive_time, adaptive_steps) = run_frames(&mut built.clone(), |physics_objects| {
+            adaptive_substeps(physics_objects, SUBSTEP_STRAIN_THRESHOLD, MAX_SUBSTEPS)
+        });
+        let (fixed_time, fixed_steps) = run_frames(&mut built.clone(), |_| MAX_SUBSTEPS);
+        assert_eq!(adaptive_steps, 60);
+        assert_eq!(fixed_steps, 60 * MAX_SUBSTEPS);
+        assert!(adaptive_time < fixed_time);
+
+        // Squeezed to half its width the strain reaches 0.5, which still gets extra substeps
+        let mut squeezed = built;
+        for particle in &mut squeezed.0 {
+            particle.position.x = 400. + (particle.position.x - 400.) * 0.5;
+        }
+        assert_eq!(
+            adaptive_substeps(&squeezed, SUBSTEP_STRAIN_THRESHOLD, MAX_SUBSTEPS),
+            3
+        );
+    }
+
+    #[test]
+    fn lattice_estimates_match_built_lattices() {
+        let canvas = ImageBuffer::from_pixel(400, 400, Rgba(DRAW_COLOR));
+        for (lattice_type, braced) in [
+            (LatticeType::Hexagonal, false),
+            (LatticeType::Square, false),
+            (LatticeType::Square, true),
+        ] {
+            let mut settings = lattice_settings(lattice_type);
+            settings.brace_squares = braced;
+            let physics_objects = build_lattice(&canvas, &settings);
+
+            let degree = lattice_type.interior_degree(braced);
+            assert_eq!(
+                most_common_degree(&particle_degrees(&physics_objects)),
+                degree
+            );
+            // What the config check expects is what the timestep report finds once it's built
+            let expected = critical_step_for(degree as f32 * settings.stiffness, 1.);
+            let critical = critical_timestep(&physics_objects, 1.).unwrap();
+            assert!((critical - expected).abs() < 1e-6 * expected.max(1.));
+
+            let estimate = lattice_type.particle_density(settings.spacing) * 400. * 400.;
+            let count = physics_objects.0.len() as f32;
+            assert!(
+                (count - estimate).abs() < 0.1 * estimate,
+                "{} particles against an estimated {}",
+                count,
+                estimate
+            );
+        }
+    }
+}